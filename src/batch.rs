@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::{read_fcs_with_options, FcsError, FcsReadOptions, FlowData};
+
+/// Read every FCS file of a directory
+///
+/// Files are found by their .fcs extension, ignoring case, and returned sorted by path. Each
+/// file gets its own result, so a malformed file does not fail the whole batch, and is read
+/// with the per-file options of `opts` like `read_fcs_with_options` does. With the
+/// `threads` feature the files are read on `opts.threads` threads. When the directory itself
+/// cannot be listed, the only result is the error of the directory.
+pub fn read_fcs_dir(dir: &str, opts: FcsReadOptions) -> Vec<(PathBuf, Result<FlowData, FcsError>)> {
+    let mut paths = Vec::new();
+    if let Err(err) = find_fcs_files(Path::new(dir), opts.recursive, &mut paths) {
+        return vec![(PathBuf::from(dir), Err(err))]
+    }
+    paths.sort();
+
+    read_all(paths, &opts)
+}
+
+// Collect the paths of the .fcs files in `dir`
+fn find_fcs_files(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>) -> Result<(), FcsError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                find_fcs_files(&path, recursive, paths)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("fcs")) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_path(path: &Path, opts: &FcsReadOptions) -> Result<FlowData, FcsError> {
+    let filename = path.to_str()
+        .ok_or_else(|| FcsError::InvalidArgument(format!("path {} is not valid UTF-8", path.display())))?;
+    read_fcs_with_options(filename, opts)
+}
+
+#[cfg(not(feature = "threads"))]
+fn read_all(paths: Vec<PathBuf>, opts: &FcsReadOptions) -> Vec<(PathBuf, Result<FlowData, FcsError>)> {
+    paths.into_iter()
+        .map(|path| {
+            let result = read_path(&path, opts);
+            (path, result)
+        })
+        .collect()
+}
+
+// Split the files in contiguous chunks, one per thread, keeping the results in path order.
+// Every file of a chunk whose thread panicked gets an Io error.
+#[cfg(feature = "threads")]
+fn read_all(paths: Vec<PathBuf>, opts: &FcsReadOptions) -> Vec<(PathBuf, Result<FlowData, FcsError>)> {
+    let threads = opts.threads
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .max(1);
+    let chunk_size = paths.len().div_ceil(threads).max(1);
+
+    let results: Vec<Vec<Result<FlowData, FcsError>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = paths.chunks(chunk_size)
+            .map(|chunk| (chunk.len(), scope.spawn(move || chunk.iter().map(|path| read_path(path, opts)).collect())))
+            .collect();
+        handles.into_iter()
+            .map(|(len, handle)| handle.join().unwrap_or_else(|_| {
+                (0..len)
+                    .map(|_| Err(FcsError::Io(std::io::Error::other("reading an FCS file panicked"))))
+                    .collect()
+            }))
+            .collect()
+    });
+
+    paths.into_iter().zip(results.into_iter().flatten()).collect()
+}
@@ -0,0 +1,155 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{self, File};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{BufWriter, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{FlowData, FcsError, Metadata, Parameter};
+
+/// Options for importing event data from a CSV file.
+#[derive(Debug, Clone)]
+pub struct CsvImportOptions {
+    /// Character separating fields in a row
+    pub separator: char,
+    /// Whether the first row holds parameter names. Without a header, parameters are
+    /// named P1, P2, etc.
+    pub has_header: bool,
+    /// FCS version recorded in the synthesized metadata
+    pub version: String,
+    /// Delimitter used for the text segment when the data is written as FCS
+    pub delimitter: u8
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        CsvImportOptions {
+            separator: ',',
+            has_header: true,
+            version: "FCS3.1".to_string(),
+            delimitter: b'|'
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Parameter {
+    /// Write the events to a single column CSV file, one value per line, preceded by the
+    /// parameter id when `header` is true
+    pub fn to_csv(&self, path: &str, header: bool) -> Result<(), FcsError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        if header {
+            writeln!(writer, "{}", self.id)?;
+        }
+        for value in self.events.iter() {
+            writeln!(writer, "{}", value)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FlowData {
+    /// Construct FlowData from a CSV file
+    ///
+    /// Each column becomes a parameter named after its header ($PnN) and each numeric row
+    /// becomes an event. The required keywords are synthesized so the result can be written
+    /// with `write_fcs`. Events are stored as doubles ($DATATYPE D) so no precision is lost.
+    pub fn from_csv(path: &str, opts: CsvImportOptions) -> Result<FlowData, FcsError> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let mut names: Vec<String> = Vec::new();
+        let mut data: Vec<Parameter> = Vec::new();
+
+        if opts.has_header {
+            let (_, header) = lines.next().ok_or(FcsError::InvalidCsv {
+                line: 1,
+                message: "missing header row".to_string()
+            })?;
+            names = header.split(opts.separator).map(|name| name.trim().to_string()).collect();
+        }
+
+        for (i, line) in lines {
+            let fields: Vec<&str> = line.split(opts.separator).map(|field| field.trim()).collect();
+
+            if names.is_empty() {
+                names = (1..=fields.len()).map(|n| format!("P{}", n)).collect();
+            }
+            if data.is_empty() {
+                data = names.iter()
+                    .map(|name| Parameter { id: name.to_string(), events: Vec::new() })
+                    .collect();
+            }
+
+            if fields.len() != data.len() {
+                return Err(FcsError::InvalidCsv {
+                    line: i+1,
+                    message: format!("expected {} fields, found {}", data.len(), fields.len())
+                })
+            }
+
+            for (parameter, field) in data.iter_mut().zip(fields) {
+                let value = field.parse::<f64>().map_err(|_| FcsError::InvalidCsv {
+                    line: i+1,
+                    message: format!("{:?} is not a number", field)
+                })?;
+                parameter.events.push(value);
+            }
+        }
+
+        if data.is_empty() {
+            return Err(FcsError::InvalidCsv {
+                line: 1,
+                message: "no parameters found".to_string()
+            })
+        }
+
+        let metadata = synthesize_metadata(&data, &opts);
+        Ok(FlowData {
+            metadata,
+            data
+        })
+    }
+}
+
+// Build the required keywords for CSV imported data
+#[cfg(not(target_arch = "wasm32"))]
+fn synthesize_metadata(data: &[Parameter], opts: &CsvImportOptions) -> Metadata {
+    let mut metadata = Metadata {
+        version: opts.version.to_owned(),
+        delimitter: opts.delimitter,
+        ..Metadata::default()
+    };
+
+    let total_events = data[0].events.len();
+    for (keyword, value) in [
+        ("$BEGINANALYSIS", "0"),
+        ("$ENDANALYSIS", "0"),
+        ("$BEGINSTEXT", "0"),
+        ("$ENDSTEXT", "0"),
+        ("$BEGINDATA", "0"),
+        ("$ENDDATA", "0"),
+        ("$MODE", "L"),
+        ("$DATATYPE", "D"),
+        ("$BYTEORD", "1,2,3,4,5,6,7,8"),
+        ("$PAR", &data.len().to_string()),
+        ("$NEXTDATA", "0"),
+        ("$TOT", &total_events.to_string()),
+    ] {
+        metadata.insert(keyword, value);
+    }
+
+    for (i, parameter) in data.iter().enumerate() {
+        let max = parameter.events.iter().cloned().fold(0.0, f64::max);
+        let range = (max.ceil() as u64).max(1);
+
+        metadata.insert(&format!("$P{}N", i+1), &parameter.id);
+        metadata.insert(&format!("$P{}B", i+1), "64");
+        metadata.insert(&format!("$P{}E", i+1), "0,0");
+        metadata.insert(&format!("$P{}R", i+1), &range.to_string());
+    }
+
+    metadata
+}
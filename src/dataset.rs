@@ -0,0 +1,122 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use crate::{read_data, read_metadata, FcsError, FcsReadOptions, FlowData, Metadata, Parameter};
+
+/// Iterator over the data sets of an fcs file
+///
+/// Data sets are found by following the $NEXTDATA links from the first data set until a
+/// $NEXTDATA of 0. Only the header and text segment of each data set is read while iterating,
+/// the data segment is read on demand from the yielded LazyFlowData.
+///
+/// $NEXTDATA is relative to the beginning of the current data set, but some writers store it
+/// relative to the beginning of the file. When the relative offset does not lead to an FCS
+/// header and the absolute one does, the absolute offset is followed.
+pub struct FcsDatasetIterator {
+    filename: String,
+    reader: BufReader<File>,
+    next_offset: Option<u64>
+}
+
+/// A data set whose metadata has been read but whose data segment has not.
+pub struct LazyFlowData {
+    pub metadata: Metadata,
+    filename: String,
+    offset: u64
+}
+
+impl FcsDatasetIterator {
+    /// Open an fcs file for iterating over its data sets
+    pub fn open(filename: &str) -> Result<FcsDatasetIterator, FcsError> {
+        let file = File::open(filename)?;
+        Ok(FcsDatasetIterator {
+            filename: filename.to_string(),
+            reader: BufReader::new(file),
+            next_offset: Some(0)
+        })
+    }
+}
+
+impl FcsDatasetIterator {
+    // Check whether an FCS header starts at the given offset of the file
+    fn starts_with_header(&mut self, offset: u64) -> Result<bool, FcsError> {
+        let mut magic = [0u8; 3];
+        self.reader.seek(SeekFrom::Start(offset))?;
+        match self.reader.read_exact(&mut magic) {
+            Ok(()) => Ok(&magic == b"FCS"),
+            Err(_) => Ok(false)
+        }
+    }
+}
+
+impl Iterator for FcsDatasetIterator {
+    type Item = Result<LazyFlowData, FcsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.next_offset.take()?;
+
+        let metadata = match read_metadata(&mut self.reader, offset) {
+            Ok(metadata) => metadata,
+            Err(err) => return Some(Err(err))
+        };
+
+        // $NEXTDATA is relative to the beginning of the current data set, 0 marks the last one
+        let next_data = metadata.values.get("$NEXTDATA").map(|v| v.as_str()).unwrap_or("0");
+        let invalid = || FcsError::InvalidKeywordValue {
+            keyword: "$NEXTDATA".to_string(),
+            value: next_data.to_string()
+        };
+        match next_data.trim().parse::<u64>() {
+            Ok(0) => {},
+            Ok(next) => {
+                let relative = match offset.checked_add(next) {
+                    Some(relative) => relative,
+                    None => return Some(Err(invalid()))
+                };
+                let absolute_instead = next > offset
+                    && !self.starts_with_header(relative).unwrap_or(false)
+                    && self.starts_with_header(next).unwrap_or(false);
+                self.next_offset = Some(if absolute_instead { next } else { relative });
+            },
+            Err(_) => return Some(Err(invalid()))
+        }
+
+        Some(Ok(LazyFlowData {
+            metadata,
+            filename: self.filename.to_owned(),
+            offset
+        }))
+    }
+}
+
+impl LazyFlowData {
+    /// Byte offset of the data set from the beginning of the file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Read the parameter event data of this data set
+    pub fn read_data(&self) -> Result<Vec<Parameter>, FcsError> {
+        let file = File::open(&self.filename)?;
+        let mut reader = BufReader::new(file);
+        read_data(&mut reader, &self.metadata, self.offset, &FcsReadOptions::default(), &mut |_| ())
+    }
+
+    /// Read the data segment and convert into a FlowData struct
+    pub fn load(self) -> Result<FlowData, FcsError> {
+        let data = self.read_data()?;
+        Ok(FlowData {
+            metadata: self.metadata,
+            data
+        })
+    }
+}
+
+/// Read every data set of an FCS file
+///
+/// Follows the $NEXTDATA links from the first data set, see `FcsDatasetIterator`, and reads
+/// each data set in full. Fails on the first data set that cannot be read.
+pub fn read_fcs_all(filename: &str) -> Result<Vec<FlowData>, FcsError> {
+    FcsDatasetIterator::open(filename)?
+        .map(|dataset| dataset?.load())
+        .collect()
+}
@@ -0,0 +1,155 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while reading, validating, converting, or writing FCS data.
+#[derive(Debug)]
+pub enum FcsError {
+    /// An underlying I/O error
+    Io(io::Error),
+    /// The header segment names an FCS version that is not supported
+    UnsupportedVersion(String),
+    /// The header segment is malformed or the file ends before it does
+    InvalidHeader(String),
+    /// A CSV file could not be parsed, with the 1-based line number where parsing failed
+    InvalidCsv { line: usize, message: String },
+    /// A keyword required for the requested operation is missing
+    MissingKeyword(String),
+    /// A keyword is neither a standard keyword nor a valid parameter keyword
+    InvalidKeyword(String),
+    /// A keyword template such as $PnN was written literally instead of with an index
+    UnexpandedTemplate(String),
+    /// A keyword is present but its value could not be interpreted
+    InvalidKeywordValue { keyword: String, value: String },
+    /// The $DATATYPE is not one that can be encoded or decoded
+    UnsupportedDataType(String),
+    /// The $BYTEORD is not one that can be encoded or decoded. Carries the raw value, the byte
+    /// positions that could be parsed from it, and a canonical order suggested in its place.
+    UnsupportedByteOrder { byte_order: String, parsed: Vec<usize>, suggestion: Option<String> },
+    /// Parameters do not all hold the same number of events
+    InconsistentEventCounts,
+    /// No parameter with the given name ($PnN) exists
+    ParameterNotFound(String),
+    /// A parameter with the given name ($PnN) already exists
+    DuplicateParameter(String),
+    /// A spillover matrix could not be parsed or estimated
+    InvalidSpillover(String),
+    /// The data segment holds fewer events than $TOT declares, or fewer histogram counts than
+    /// the bins declared by $PnR
+    TruncatedData { expected: usize, available: usize },
+    /// Compensation grew the magnitude of the listed parameters beyond the allowed factor
+    SuspiciousCompensation(Vec<String>),
+    /// An argument passed to a function is out of its valid range
+    InvalidArgument(String),
+    /// A value of an ASCII ($DATATYPE A) data segment is not a number
+    InvalidAsciiValue(String),
+    /// The number of values ($PAR * $TOT) does not fit in memory addresses of this target
+    DataTooLarge { parameters: usize, events: usize },
+    /// An updated text segment does not fit in the space taken by the original one
+    TextSegmentTooLarge { required: u64, available: u64 },
+    /// The range ($PnR) of the named parameter needs more bits than its $PnB stores
+    RangeExceedsBitWidth { parameter: String },
+    /// The number of $BYTEORD entries does not match the width in bytes of the $DATATYPE values
+    ByteOrderWidthMismatch { byte_order: String, width: usize },
+    /// A keyword cannot be represented in the FCS version a data set is converted to
+    IncompatibleKeyword { keyword: String, version: String },
+}
+
+impl fmt::Display for FcsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FcsError::Io(err) => write!(f, "I/O error: {}", err),
+            FcsError::UnsupportedVersion(version) => write!(f, "FCS version {} not supported", version),
+            FcsError::InvalidHeader(message) => write!(f, "Invalid header segment: {}", message),
+            FcsError::InvalidCsv { line, message } => write!(f, "Invalid CSV at line {}: {}", line, message),
+            FcsError::MissingKeyword(keyword) => write!(f, "Required keyword {} is missing", keyword),
+            FcsError::InvalidKeyword(keyword) => write!(f, "Keyword {} is not a valid keyword", keyword),
+            FcsError::UnexpandedTemplate(keyword) => {
+                write!(f, "Keyword {} is an unexpanded template, expected an index in place of n", keyword)
+            },
+            FcsError::InvalidKeywordValue { keyword, value } => {
+                write!(f, "Invalid value {:?} for keyword {}", value, keyword)
+            },
+            FcsError::UnsupportedDataType(data_type) => write!(f, "Data type {} not supported", data_type),
+            FcsError::UnsupportedByteOrder { byte_order, suggestion, .. } => {
+                write!(f, "Byte order {} not supported", byte_order)?;
+                match suggestion {
+                    Some(suggestion) => write!(f, ", did you mean {}?", suggestion),
+                    None => Ok(())
+                }
+            },
+            FcsError::InconsistentEventCounts => write!(f, "Parameters do not all have the same number of events"),
+            FcsError::ParameterNotFound(name) => write!(f, "Parameter {} not found", name),
+            FcsError::DuplicateParameter(name) => write!(f, "Parameter {} already exists", name),
+            FcsError::InvalidSpillover(message) => write!(f, "Invalid spillover matrix: {}", message),
+            FcsError::TruncatedData { expected, available } => {
+                write!(f, "Data segment holds {} of the {} declared events or histogram counts", available, expected)
+            },
+            FcsError::SuspiciousCompensation(parameters) => {
+                write!(f, "Compensation grew the range of {} unexpectedly, check the spillover matrix", parameters.join(", "))
+            },
+            FcsError::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            FcsError::InvalidAsciiValue(value) => write!(f, "Invalid ASCII data value {:?}", value),
+            FcsError::DataTooLarge { parameters, events } => {
+                write!(f, "Data set of {} parameters by {} events is too large for this target", parameters, events)
+            },
+            FcsError::TextSegmentTooLarge { required, available } => write!(
+                f,
+                "Updated text segment needs {} bytes but only {} are available, rewrite the file with write_fcs instead",
+                required, available
+            ),
+            FcsError::RangeExceedsBitWidth { parameter } => {
+                write!(f, "Range ($PnR) of parameter {} cannot be stored in its bit width ($PnB)", parameter)
+            },
+            FcsError::ByteOrderWidthMismatch { byte_order, width } => {
+                write!(f, "Byte order {} does not describe the {} bytes of each value", byte_order, width)
+            },
+            FcsError::IncompatibleKeyword { keyword, version } => {
+                write!(f, "Keyword {} cannot be represented in {}", keyword, version)
+            },
+        }
+    }
+}
+
+impl FcsError {
+    // Build an UnsupportedByteOrder error, suggesting plain little or big endian order depending
+    // on whether the parsed positions mostly increase or decrease
+    pub(crate) fn unsupported_byte_order(byte_order: &str) -> FcsError {
+        let parsed: Vec<usize> = byte_order.split(|c: char| !c.is_ascii_digit())
+            .filter_map(|b| b.parse::<usize>().ok())
+            .collect();
+
+        let suggestion = match (parsed.first(), parsed.last()) {
+            (Some(first), Some(last)) if parsed.len() > 1 => {
+                let width = if parsed.len() > 4 { 8 } else { 4 };
+                let mut order: Vec<String> = (1..=width).map(|b| b.to_string()).collect();
+                if first > last {
+                    order.reverse();
+                }
+                Some(order.join(","))
+            },
+            _ => None
+        };
+
+        FcsError::UnsupportedByteOrder {
+            byte_order: byte_order.to_string(),
+            parsed,
+            suggestion
+        }
+    }
+}
+
+impl Error for FcsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FcsError::Io(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for FcsError {
+    fn from(err: io::Error) -> Self {
+        FcsError::Io(err)
+    }
+}
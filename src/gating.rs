@@ -0,0 +1,383 @@
+use std::collections::BTreeMap;
+use crate::transform::Scale;
+use crate::{FcsError, FlowData, Parameter};
+
+// Upper bound on k-means iterations in case the assignments keep oscillating
+const MAX_KMEANS_ITERATIONS: usize = 100;
+
+/// Window of a gating region ($RnW)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Window {
+    /// Inclusive (min, max) interval on a single parameter
+    Interval(f64, f64),
+    /// Polygon vertices (x, y) on a pair of parameters
+    Polygon(Vec<(f64, f64)>)
+}
+
+/// Gating region defined by the $RnI and $RnW keywords
+///
+/// Region windows are drawn on the recommended display scale of their parameters, so each
+/// region carries the scale of every parameter it references: stored values are converted to
+/// linear values when $PnE gives log amplification, then to log10 values when $PnD gives a
+/// logarithmic display.
+#[derive(Debug, Clone)]
+pub struct Region {
+    /// Region number n
+    pub id: usize,
+    /// Zero-based indices of the parameters the region applies to
+    pub parameters: Vec<usize>,
+    pub window: Window,
+    scales: Vec<Scale>
+}
+
+impl Region {
+    /// Test every event for membership in the region
+    ///
+    /// Event values are converted to the scale of the region's parameters before they are
+    /// compared to the window, so regions defined on log scales can be applied to raw data.
+    pub fn apply(&self, flowdata: &FlowData) -> Result<Vec<bool>, FcsError> {
+        let total_events = flowdata.event_count()?;
+        let mut columns = Vec::with_capacity(self.parameters.len());
+        for index in self.parameters.iter() {
+            let parameter = flowdata.data.get(*index)
+                .ok_or_else(|| FcsError::ParameterNotFound(format!("$P{}N", index+1)))?;
+            columns.push(&parameter.events);
+        }
+
+        let value = |axis: usize, event: usize| self.scales[axis].apply(columns[axis][event]);
+        let mask = match &self.window {
+            Window::Interval(min, max) => (0..total_events)
+                .map(|event| {
+                    let x = value(0, event);
+                    x >= *min && x <= *max
+                })
+                .collect(),
+            Window::Polygon(vertices) => (0..total_events)
+                .map(|event| point_in_polygon(value(0, event), value(1, event), vertices))
+                .collect()
+        };
+
+        Ok(mask)
+    }
+}
+
+/// Events kept by a gate together with the "% of parent" statistic
+pub struct GateResult {
+    /// Events inside the gate
+    pub data: FlowData,
+    /// Number of events before gating
+    pub input_events: usize,
+    /// Number of events inside the gate
+    pub output_events: usize,
+    /// Percentage of the input events inside the gate, 0 when there are no input events
+    pub percent: f64
+}
+
+/// Rectangular gate on a pair of parameters, as suggested by `FlowData::suggest_gates`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RectGate {
+    /// Parameter name ($PnN) on the x axis
+    pub x: String,
+    /// Parameter name ($PnN) on the y axis
+    pub y: String,
+    /// Inclusive (min, max) range on the x axis
+    pub x_range: (f64, f64),
+    /// Inclusive (min, max) range on the y axis
+    pub y_range: (f64, f64),
+    /// Number of events of the cluster the gate was drawn around
+    pub events: usize
+}
+
+impl RectGate {
+    /// Keep the events of `flowdata` inside the gate
+    pub fn apply(&self, flowdata: &FlowData) -> Result<FlowData, FcsError> {
+        flowdata.gate_rectangle(&self.x, &self.y, self.x_range, self.y_range)
+    }
+}
+
+impl FlowData {
+    /// Keep the events whose `x` and `y` parameter values ($PnN) fall in the inclusive
+    /// rectangle spanned by `x_range` and `y_range`
+    pub fn gate_rectangle(&self, x: &str, y: &str, x_range: (f64, f64), y_range: (f64, f64)) -> Result<FlowData, FcsError> {
+        Ok(self.gate_rectangle_with_stats(x, y, x_range, y_range)?.data)
+    }
+
+    /// Same as `gate_rectangle`, also reporting the fraction of events inside the gate
+    pub fn gate_rectangle_with_stats(&self, x: &str, y: &str, x_range: (f64, f64), y_range: (f64, f64)) -> Result<GateResult, FcsError> {
+        let inside = |min: f64, max: f64, v: f64| v >= min.min(max) && v <= min.max(max);
+        let xs = &self.data[self.parameter_index(x)?].events;
+        let ys = &self.data[self.parameter_index(y)?].events;
+        let mask: Vec<bool> = xs.iter().zip(ys.iter())
+            .map(|(&xv, &yv)| inside(x_range.0, x_range.1, xv) && inside(y_range.0, y_range.1, yv))
+            .collect();
+        self.gate_result(&mask)
+    }
+
+    /// Keep the events whose `x` and `y` parameter values ($PnN) fall inside the polygon
+    /// with the given vertices
+    pub fn gate_polygon(&self, x: &str, y: &str, vertices: &[(f64, f64)]) -> Result<FlowData, FcsError> {
+        Ok(self.gate_polygon_with_stats(x, y, vertices)?.data)
+    }
+
+    /// Same as `gate_polygon`, also reporting the fraction of events inside the gate
+    pub fn gate_polygon_with_stats(&self, x: &str, y: &str, vertices: &[(f64, f64)]) -> Result<GateResult, FcsError> {
+        let xs = &self.data[self.parameter_index(x)?].events;
+        let ys = &self.data[self.parameter_index(y)?].events;
+        let mask: Vec<bool> = if vertices.len() < 3 {
+            vec![false; xs.len()]
+        } else {
+            xs.iter().zip(ys.iter())
+                .map(|(&xv, &yv)| point_in_polygon(xv, yv, vertices))
+                .collect()
+        };
+        self.gate_result(&mask)
+    }
+
+    /// Values of the `target` parameter for the events whose `gate_param` value lies in the
+    /// inclusive range `min..=max`, both given by name ($PnN)
+    pub fn parameter_where(&self, target: &str, gate_param: &str, min: f64, max: f64) -> Result<Vec<f64>, FcsError> {
+        let target = &self.data[self.parameter_index(target)?].events;
+        let gate = &self.data[self.parameter_index(gate_param)?].events;
+        Ok(target.iter()
+            .zip(gate.iter())
+            .filter(|(_, g)| **g >= min && **g <= max)
+            .map(|(value, _)| *value)
+            .collect())
+    }
+
+    /// Suggest rectangular gates around the `k` densest clusters of the `x` and `y` parameters
+    ///
+    /// Events are clustered with k-means on both parameters scaled to their observed range, so
+    /// neither axis dominates the distance, and each gate is the bounding box of a cluster.
+    /// Centroids are seeded deterministically by farthest point traversal, so the same data
+    /// always gives the same gates. Clusters left without events are dropped and the gates are
+    /// sorted by the center of their x range.
+    pub fn suggest_gates(&self, x: &str, y: &str, k: usize) -> Result<Vec<RectGate>, FcsError> {
+        let xs = &self.data[self.parameter_index(x)?].events;
+        let ys = &self.data[self.parameter_index(y)?].events;
+        if k == 0 || k > xs.len() {
+            return Err(FcsError::InvalidArgument(format!("cannot form {} clusters from {} events", k, xs.len())))
+        }
+
+        let normalize = |values: &[f64]| -> Vec<f64> {
+            let (min, max) = values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(*v), max.max(*v)));
+            let span = if max > min { max - min } else { 1.0 };
+            values.iter().map(|v| (v - min) / span).collect()
+        };
+        let points: Vec<(f64, f64)> = normalize(xs).into_iter().zip(normalize(ys)).collect();
+        let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2);
+
+        // seed with the first event, then repeatedly the event farthest from all centroids
+        let mut centroids = vec![points[0]];
+        let mut nearest: Vec<f64> = points.iter().map(|&p| distance(p, points[0])).collect();
+        while centroids.len() < k {
+            let (farthest, _) = nearest.iter().enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap();
+            let seed = points[farthest];
+            centroids.push(seed);
+            for (d, &p) in nearest.iter_mut().zip(points.iter()) {
+                *d = d.min(distance(p, seed));
+            }
+        }
+
+        let closest = |p: (f64, f64), centroids: &[(f64, f64)]| -> usize {
+            (0..centroids.len())
+                .min_by(|&a, &b| distance(p, centroids[a]).total_cmp(&distance(p, centroids[b])))
+                .unwrap()
+        };
+        let mut labels: Vec<usize> = points.iter().map(|&p| closest(p, &centroids)).collect();
+        for _ in 0..MAX_KMEANS_ITERATIONS {
+            let mut sums = vec![(0.0, 0.0, 0usize); k];
+            for (&(px, py), &label) in points.iter().zip(labels.iter()) {
+                sums[label].0 += px;
+                sums[label].1 += py;
+                sums[label].2 += 1;
+            }
+            for (centroid, (sx, sy, n)) in centroids.iter_mut().zip(sums) {
+                if n > 0 {
+                    *centroid = (sx / n as f64, sy / n as f64);
+                }
+            }
+
+            let updated: Vec<usize> = points.iter().map(|&p| closest(p, &centroids)).collect();
+            if updated == labels {
+                break
+            }
+            labels = updated;
+        }
+
+        let mut gates: Vec<RectGate> = (0..k)
+            .filter_map(|cluster| {
+                let members: Vec<usize> = (0..labels.len()).filter(|&i| labels[i] == cluster).collect();
+                if members.is_empty() {
+                    return None
+                }
+                let range = |values: &[f64]| members.iter()
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &i| (min.min(values[i]), max.max(values[i])));
+                Some(RectGate {
+                    x: x.to_string(),
+                    y: y.to_string(),
+                    x_range: range(xs),
+                    y_range: range(ys),
+                    events: members.len()
+                })
+            })
+            .collect();
+        gates.sort_by(|a, b| (a.x_range.0 + a.x_range.1).total_cmp(&(b.x_range.0 + b.x_range.1)));
+
+        Ok(gates)
+    }
+
+    /// Copy of the data set keeping the events where `mask` is true, with $TOT updated
+    ///
+    /// Meant for gates computed outside of this crate. The mask needs one entry per event,
+    /// otherwise an InvalidArgument error is returned.
+    pub fn apply_mask(&self, mask: &[bool]) -> Result<FlowData, FcsError> {
+        let total_events = self.event_count()?;
+        if mask.len() != total_events {
+            return Err(FcsError::InvalidArgument(format!(
+                "mask has {} entries but the data set holds {} events", mask.len(), total_events
+            )))
+        }
+        Ok(self.filter_events(mask))
+    }
+
+    /// Deterministic downsampling keeping every `n`th event, at indices 0, n, 2n, ...
+    ///
+    /// Returns a copy of the data set with $TOT updated, or an InvalidArgument error when `n`
+    /// is 0.
+    pub fn stride(&self, n: usize) -> Result<FlowData, FcsError> {
+        if n == 0 {
+            return Err(FcsError::InvalidArgument("stride must be at least 1".to_string()))
+        }
+        let total_events = self.data.first().map_or(0, |parameter| parameter.events.len());
+        let mask: Vec<bool> = (0..total_events).map(|i| i % n == 0).collect();
+        Ok(self.filter_events(&mask))
+    }
+
+    // Keep the events selected by `mask` and count them
+    fn gate_result(&self, mask: &[bool]) -> Result<GateResult, FcsError> {
+        let input_events = self.event_count()?;
+        let data = self.filter_events(mask);
+        let output_events = mask.iter().filter(|keep| **keep).count();
+        let percent = if input_events == 0 {
+            0.0
+        } else {
+            100.0 * output_events as f64 / input_events as f64
+        };
+
+        Ok(GateResult {
+            data,
+            input_events,
+            output_events,
+            percent
+        })
+    }
+
+    // Copy of the data set holding only the events selected by `mask`, with $TOT updated
+    pub(crate) fn filter_events(&self, mask: &[bool]) -> FlowData {
+        let data: Vec<Parameter> = self.data.iter()
+            .map(|parameter| Parameter {
+                id: parameter.id.to_owned(),
+                events: parameter.events.iter()
+                    .zip(mask.iter())
+                    .filter(|(_, keep)| **keep)
+                    .map(|(value, _)| *value)
+                    .collect()
+            })
+            .collect();
+
+        let mut metadata = self.metadata.clone();
+        let total_events = data.first().map(|p| p.events.len()).unwrap_or(0);
+        metadata.insert("$TOT", &total_events.to_string());
+
+        FlowData {
+            metadata,
+            data
+        }
+    }
+
+    /// Gating regions described by the $RnI/$RnW keyword pairs, in region number order
+    ///
+    /// $RnI holds the parameter number, or a pair of comma separated parameter numbers, and
+    /// $RnW the window: "min,max" for a single parameter, or ";" separated "x,y" polygon
+    /// vertices for a pair of parameters.
+    pub fn regions(&self) -> Result<Vec<Region>, FcsError> {
+        let mut numbers = BTreeMap::new();
+        for keyword in self.metadata.keywords.iter() {
+            let number = keyword.strip_prefix("$R")
+                .and_then(|k| k.strip_suffix('I'))
+                .and_then(|n| n.parse::<usize>().ok());
+            if let Some(number) = number {
+                numbers.insert(number, keyword.to_owned());
+            }
+        }
+
+        numbers.into_iter()
+            .map(|(number, keyword)| self.region(number, &keyword))
+            .collect()
+    }
+
+    // Parse the region with the given number from its $RnI keyword and matching $RnW
+    fn region(&self, number: usize, keyword: &str) -> Result<Region, FcsError> {
+        let value = &self.metadata.values[keyword];
+        let invalid = |keyword: &str, value: &str| FcsError::InvalidKeywordValue {
+            keyword: keyword.to_string(),
+            value: value.to_string()
+        };
+
+        let parameters = value.split(',')
+            .map(|p| match p.trim().parse::<usize>() {
+                Ok(n) if n >= 1 && n <= self.data.len() => Ok(n - 1),
+                _ => Err(invalid(keyword, value))
+            })
+            .collect::<Result<Vec<usize>, FcsError>>()?;
+
+        let window_keyword = format!("$R{}W", number);
+        let window_value = self.metadata.values.get(&window_keyword)
+            .ok_or_else(|| FcsError::MissingKeyword(window_keyword.clone()))?;
+        let points = window_value.split(';')
+            .map(|point| {
+                let point = point.trim().trim_start_matches('(').trim_end_matches(')');
+                match point.split(',').map(|c| c.trim().parse::<f64>()).collect::<Result<Vec<f64>, _>>() {
+                    Ok(coords) if coords.len() == 2 => Ok((coords[0], coords[1])),
+                    _ => Err(invalid(&window_keyword, window_value))
+                }
+            })
+            .collect::<Result<Vec<(f64, f64)>, FcsError>>()?;
+
+        let window = match (parameters.len(), points.as_slice()) {
+            (1, [(min, max)]) => Window::Interval(min.min(*max), min.max(*max)),
+            (2, vertices) if vertices.len() >= 3 => Window::Polygon(points),
+            (1 | 2, _) => return Err(invalid(&window_keyword, window_value)),
+            _ => return Err(invalid(keyword, value))
+        };
+
+        let scales = parameters.iter()
+            .map(|index| self.recommended_scale(*index))
+            .collect::<Result<Vec<Scale>, FcsError>>()?;
+
+        Ok(Region {
+            id: number,
+            parameters,
+            window,
+            scales
+        })
+    }
+}
+
+// Even-odd rule point in polygon test
+pub(crate) fn point_in_polygon(x: f64, y: f64, vertices: &[(f64, f64)]) -> bool {
+    let mut inside = false;
+    let mut j = vertices.len() - 1;
+    for i in 0..vertices.len() {
+        let (xi, yi) = vertices[i];
+        let (xj, yj) = vertices[j];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
@@ -0,0 +1,160 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use crate::{byte_order_for_width, data_segment_offsets, decode_ordered, float_byte_order, parse_byte_order, parse_keyword, read_metadata, ColumnFormat, FcsError, Metadata};
+
+/// How the counts of a histogram mode data set are arranged
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistogramMode {
+    /// $MODE "C": a single histogram over all parameters, with $P1R * $P2R * ... bins
+    Correlated,
+    /// $MODE "U": one histogram per parameter with $PnR bins each, stored one after another
+    Uncorrelated
+}
+
+/// Bin counts of a data set stored in one of the deprecated histogram modes
+///
+/// Histogram mode data sets hold no events, so they are read with `read_histogram` rather
+/// than `read_fcs`, which only reads list mode data.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub metadata: Metadata,
+    pub mode: HistogramMode,
+    /// Parameter names ($PnN) in order
+    pub parameters: Vec<String>,
+    /// Number of bins ($PnR) of each parameter
+    pub bins: Vec<usize>,
+    /// For uncorrelated histograms, the counts of each parameter. For a correlated histogram,
+    /// a single vector of all counts in the order of the data segment.
+    pub counts: Vec<Vec<f64>>
+}
+
+/// Read a histogram mode ($MODE "C" or "U") FCS file
+///
+/// Counts are decoded with the $DATATYPE, $BYTEORD, and $PnB of the data set. A correlated
+/// histogram takes the width of $P1B for every count, and all $PnB have to agree.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_histogram(filename: &str) -> Result<Histogram, FcsError> {
+    let file = File::open(filename)?;
+    read_histogram_from(file)
+}
+
+/// Read a histogram mode FCS file from any seekable source, see `read_histogram`
+pub fn read_histogram_from<R: Read + Seek>(reader: R) -> Result<Histogram, FcsError> {
+    let mut reader = BufReader::new(reader);
+    let metadata = read_metadata(&mut reader, 0)?;
+    read_counts(&mut reader, metadata)
+}
+
+fn read_counts<R: BufRead + Seek>(reader: &mut R, metadata: Metadata) -> Result<Histogram, FcsError> {
+    let mode_value = metadata.values.get("$MODE").map_or("L", |mode| mode.trim());
+    let mode = match mode_value {
+        "C" => HistogramMode::Correlated,
+        "U" => HistogramMode::Uncorrelated,
+        _ => return Err(FcsError::InvalidKeywordValue {
+            keyword: "$MODE".to_string(),
+            value: mode_value.to_string()
+        })
+    };
+
+    let total_params: usize = parse_keyword(&metadata, "$PAR")?;
+    let mut parameters = Vec::new();
+    let mut bins = Vec::new();
+    let mut formats = Vec::new();
+    for n in 1..=total_params {
+        let name_keyword = format!("$P{}N", n);
+        parameters.push(metadata.values.get(&name_keyword)
+            .ok_or(FcsError::MissingKeyword(name_keyword))?
+            .to_owned());
+        bins.push(parse_keyword::<usize>(&metadata, &format!("$P{}R", n))?);
+        formats.push(count_format(&metadata, n)?);
+    }
+
+    // the number and width of the counts in each histogram
+    let histograms: Vec<(usize, &ColumnFormat)> = match mode {
+        HistogramMode::Uncorrelated => bins.iter().copied().zip(formats.iter()).collect(),
+        HistogramMode::Correlated => {
+            let first = match formats.first() {
+                Some(first) => first,
+                None => return Err(FcsError::MissingKeyword("$P1B".to_string()))
+            };
+            if let Some(n) = formats.iter().position(|format| format.width() != first.width()) {
+                return Err(FcsError::InvalidKeywordValue {
+                    keyword: format!("$P{}B", n+1),
+                    value: metadata.values.get(&format!("$P{}B", n+1)).cloned().unwrap_or_default()
+                })
+            }
+            let total_bins = bins.iter().try_fold(1usize, |total, &bins| total.checked_mul(bins));
+            vec![(total_bins.ok_or(FcsError::DataTooLarge { parameters: total_params, events: 0 })?, first)]
+        }
+    };
+
+    // check the size before allocating, a bogus $PnR must not exhaust memory
+    let (start_offset, end_offset) = data_segment_offsets(&metadata)?;
+    let segment_bytes = end_offset.saturating_add(1).saturating_sub(start_offset);
+    let required = histograms.iter()
+        .try_fold(0u64, |total, (count, format)| (*count as u64).checked_mul(format.width() as u64)
+            .and_then(|bytes| total.checked_add(bytes)))
+        .ok_or(FcsError::DataTooLarge { parameters: total_params, events: 0 })?;
+    if required > segment_bytes {
+        // the counts of every histogram that fit in the data segment, in storage order
+        let mut remaining = segment_bytes;
+        let available = histograms.iter()
+            .map(|(count, format)| {
+                let fit = (remaining / format.width() as u64).min(*count as u64);
+                remaining -= fit * format.width() as u64;
+                fit as usize
+            })
+            .sum();
+        return Err(FcsError::TruncatedData {
+            expected: histograms.iter().map(|(count, _)| count).sum(),
+            available
+        })
+    }
+
+    reader.seek(SeekFrom::Start(start_offset))?;
+    let mut counts = Vec::with_capacity(histograms.len());
+    for (count, format) in histograms {
+        let width = format.width();
+        let mut bytes = vec![0u8; count * width];
+        reader.read_exact(&mut bytes)?;
+        counts.push(bytes.chunks_exact(width)
+            .map(|b| match format {
+                ColumnFormat::Integer(order, _) => decode_ordered(b, order) as f64,
+                ColumnFormat::Float(order) => f32::from_bits(decode_ordered(b, order) as u32) as f64,
+                ColumnFormat::Double(order) => f64::from_bits(decode_ordered(b, order)),
+                _ => unreachable!("count formats are numeric")
+            })
+            .collect());
+    }
+
+    Ok(Histogram {
+        metadata,
+        mode,
+        parameters,
+        bins,
+        counts
+    })
+}
+
+// Format of the counts of parameter `n`, taking $PnB bits
+fn count_format(metadata: &Metadata, n: usize) -> Result<ColumnFormat, FcsError> {
+    let data_type: String = parse_keyword(metadata, "$DATATYPE")?;
+    let byte_order: String = parse_keyword(metadata, "$BYTEORD")?;
+    let bits_keyword = format!("$P{}B", n);
+    let bits: usize = parse_keyword(metadata, &bits_keyword)?;
+
+    match (data_type.as_str(), bits) {
+        ("I", 8 | 16 | 32 | 64) => parse_byte_order(&byte_order)
+            .and_then(|order| byte_order_for_width(&order, bits / 8))
+            .map(|order| ColumnFormat::Integer(order, u64::MAX))
+            .ok_or_else(|| FcsError::unsupported_byte_order(&byte_order)),
+        ("F", 32) => Ok(ColumnFormat::Float(float_byte_order(&byte_order, 4)?)),
+        ("D", 64) | ("F", 64) => Ok(ColumnFormat::Double(float_byte_order(&byte_order, 8)?)),
+        ("I" | "F" | "D", _) => Err(FcsError::InvalidKeywordValue {
+            keyword: bits_keyword,
+            value: bits.to_string()
+        }),
+        _ => Err(FcsError::UnsupportedDataType(data_type))
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{FcsError, FlowData, Metadata};
+
+/// Row of a marker panel summary, assembled from the parameter keywords
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanelEntry {
+    /// Parameter name ($PnN)
+    pub detector: String,
+    /// Stain or marker name ($PnS)
+    pub marker: Option<String>,
+    /// Excitation wavelength in nm ($PnL), the first one when several are listed
+    pub laser: Option<f64>,
+    /// Optical filter ($PnF)
+    pub filter: Option<String>,
+    /// Detector type such as PMT or APD ($PnT)
+    pub detector_type: Option<String>
+}
+
+/// Sample description keywords of a data set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SampleInfo {
+    /// Type of objects measured ($CELLS)
+    pub cells: Option<String>,
+    /// Source of the specimen, e.g. patient or cell line ($SRC)
+    pub source: Option<String>,
+    /// Specimen label, e.g. the tube ($SMNO)
+    pub specimen: Option<String>,
+    /// Project name ($PROJ)
+    pub project: Option<String>,
+    /// Name of the investigator initiating the experiment ($EXP)
+    pub experiment: Option<String>
+}
+
+impl FlowData {
+    /// Summarize the marker panel, one entry per parameter in $PnN order
+    pub fn panel(&self) -> Vec<PanelEntry> {
+        let keyword = |n: usize, suffix: char| {
+            self.metadata.values.get(&format!("$P{}{}", n, suffix))
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+
+        self.data.iter().enumerate()
+            .map(|(i, parameter)| PanelEntry {
+                detector: parameter.id.to_owned(),
+                marker: keyword(i+1, 'S').map(|marker| marker.to_string()),
+                laser: keyword(i+1, 'L').and_then(|laser| laser.split(',').next()?.trim().parse().ok()),
+                filter: keyword(i+1, 'F').map(|filter| filter.to_string()),
+                detector_type: keyword(i+1, 'T').map(|detector_type| detector_type.to_string())
+            })
+            .collect()
+    }
+
+    /// Cell subset names for files acquired with cell subsets ($CSMODE)
+    ///
+    /// The name at index i belongs to the subset with id i + 1 and is taken from the
+    /// $CSVnFLAG keyword of that subset, or is empty when the subset has no flag. Returns
+    /// None when the file does not use cell subsets.
+    pub fn cell_subsets(&self) -> Option<Vec<String>> {
+        let total_subsets = self.metadata.values.get("$CSMODE")?.trim().parse::<usize>().ok()?;
+        if total_subsets == 0 {
+            return None
+        }
+
+        let names = (1..=total_subsets)
+            .map(|n| {
+                self.metadata.values.get(&format!("$CSV{}FLAG", n))
+                    .map(|flag| flag.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+        Some(names)
+    }
+}
+
+impl Metadata {
+    /// Number of events lost to electronic coincidence ($ABRT)
+    pub fn abort_count(&self) -> Option<u64> {
+        self.values.get("$ABRT")?.trim().parse().ok()
+    }
+
+    /// Number of events lost because the acquisition computer was busy ($LOST)
+    pub fn lost_count(&self) -> Option<u64> {
+        self.values.get("$LOST")?.trim().parse().ok()
+    }
+
+    /// Time step of the time parameter in seconds ($TIMESTEP), None when the keyword is
+    /// missing and an error when it is not a number
+    pub fn timestep(&self) -> Result<Option<f64>, FcsError> {
+        let value = match self.values.get("$TIMESTEP") {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        value.trim().parse::<f64>().map(Some).map_err(|_| FcsError::InvalidKeywordValue {
+            keyword: "$TIMESTEP".to_string(),
+            value: value.to_string()
+        })
+    }
+
+    /// Sample flow rate recorded during acquisition, None when no flow rate keyword is present
+    ///
+    /// $FLOWRATE (FCS 3.2) is preferred, otherwise vendor keywords spelled FLOWRATE, FLOW RATE,
+    /// FLOW_RATE or SAMPLE FLOW RATE, with or without a # or @ prefix and in any case, are
+    /// used. The value is the leading number, so a unit such as "60 uL/min" is left out.
+    pub fn flow_rate(&self) -> Option<f64> {
+        let normalized = |keyword: &str| keyword.trim_start_matches(['#', '@'])
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '_' | '-'))
+            .collect::<String>()
+            .to_ascii_uppercase();
+        let keyword = self.values.contains_key("$FLOWRATE")
+            .then_some("$FLOWRATE")
+            .or_else(|| self.keywords.iter()
+                .map(|keyword| keyword.as_str())
+                .find(|keyword| matches!(normalized(keyword).as_str(), "FLOWRATE" | "SAMPLEFLOWRATE")))?;
+
+        let value = self.values.get(keyword)?.trim();
+        let end = value.find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+            .unwrap_or(value.len());
+        value[..end].parse::<f64>().ok()
+    }
+
+    /// Check that the range ($PnR) of every integer parameter can be stored in its $PnB bits
+    ///
+    /// A range above 2^$PnB cannot be represented and points to a bug in the writing software.
+    /// This check is strict and not part of reading, so files are only rejected on request.
+    /// Only integer data ($DATATYPE I) is checked, as float ranges are not bounded by $PnB.
+    /// Parameters without a numeric $PnB or $PnR are skipped.
+    pub fn validate_ranges(&self) -> Result<(), FcsError> {
+        if self.values.get("$DATATYPE").map(|t| t.trim()) != Some("I") {
+            return Ok(())
+        }
+
+        let total_params = self.values.get("$PAR").and_then(|n| n.trim().parse::<usize>().ok()).unwrap_or(0);
+        for n in 1..=total_params {
+            let value = |suffix: char| self.values.get(&format!("$P{}{}", n, suffix))
+                .and_then(|value| value.trim().parse::<f64>().ok());
+            if let (Some(bits), Some(range)) = (value('B'), value('R')) {
+                if range > 2f64.powf(bits) {
+                    let parameter = self.values.get(&format!("$P{}N", n))
+                        .cloned()
+                        .unwrap_or_else(|| format!("$P{}N", n));
+                    return Err(FcsError::RangeExceedsBitWidth { parameter })
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sample description from $CELLS, $SRC, $SMNO, $PROJ, and $EXP, leaving out blank values
+    pub fn sample_info(&self) -> SampleInfo {
+        let keyword = |keyword: &str| {
+            self.values.get(keyword)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.to_string())
+        };
+
+        SampleInfo {
+            cells: keyword("$CELLS"),
+            source: keyword("$SRC"),
+            specimen: keyword("$SMNO"),
+            project: keyword("$PROJ"),
+            experiment: keyword("$EXP")
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::{FcsError, FlowData, Parameter};
+
+impl FlowData {
+    /// Number of events (rows) and parameters (columns) of the data
+    pub fn shape(&self) -> (usize, usize) {
+        let rows = self.data.first().map(|p| p.events.len()).unwrap_or(0);
+        (rows, self.data.len())
+    }
+
+    /// Values of event `i` for every parameter in $PnN order, None when a parameter holds
+    /// fewer than `i + 1` events
+    pub fn event(&self, i: usize) -> Option<Vec<f64>> {
+        self.data.iter()
+            .map(|parameter| parameter.events.get(i).copied())
+            .collect()
+    }
+
+    /// Iterate over the events in acquisition order, yielding the values of each event for
+    /// every parameter in $PnN order
+    ///
+    /// Only one event is copied at a time, the data is not transposed up front. When
+    /// parameters hold different numbers of events, iteration stops at the shortest one.
+    pub fn events(&self) -> impl Iterator<Item = Vec<f64>> + '_ {
+        let rows = self.data.iter().map(|parameter| parameter.events.len()).min().unwrap_or(0);
+        (0..rows).map(move |i| self.data.iter().map(|parameter| parameter.events[i]).collect())
+    }
+
+    /// Copy the data into a single contiguous column-major buffer
+    ///
+    /// The buffer holds `rows * cols` values as given by `shape`, with the events of each
+    /// parameter stored contiguously in $PnN order. The value of event `i` for parameter `j`
+    /// is at index `j * rows + i`, which is the layout expected by BLAS/LAPACK routines with a
+    /// leading dimension of `rows`.
+    pub fn to_column_major(&self) -> Result<Vec<f64>, FcsError> {
+        let (rows, cols) = (self.event_count()?, self.data.len());
+        let mut buffer = Vec::with_capacity(rows * cols);
+        for parameter in self.data.iter() {
+            buffer.extend_from_slice(&parameter.events);
+        }
+        Ok(buffer)
+    }
+
+    /// Append the parameters of `other` after the parameters of this data set
+    ///
+    /// Both data sets must hold the same number of events and no parameter name ($PnN) may
+    /// appear in both. The parameter keywords of `other` ($PnN, $PnB, $PnR, ...) are copied
+    /// with their index shifted past the existing parameters, and $PAR is updated.
+    pub fn join_parameters(&mut self, other: &FlowData) -> Result<(), FcsError> {
+        let total_events = self.event_count()?;
+        if !self.data.is_empty() && !other.data.is_empty() && other.event_count()? != total_events {
+            return Err(FcsError::InconsistentEventCounts)
+        }
+        if let Some(duplicate) = other.data.iter().find(|p| self.data.iter().any(|q| q.id == p.id)) {
+            return Err(FcsError::DuplicateParameter(duplicate.id.to_owned()))
+        }
+
+        let offset = self.data.len();
+        for keyword in other.metadata.keywords.iter() {
+            let index = keyword.strip_prefix("$P")
+                .map(|rest| rest.split(|c: char| !c.is_ascii_digit()).next().unwrap_or(""))
+                .and_then(|digits| Some((digits.parse::<usize>().ok()?, digits.len())));
+            if let Some((n, digits)) = index {
+                let suffix = &keyword[2 + digits..];
+                if n >= 1 && n <= other.data.len() && !suffix.is_empty() {
+                    let value = &other.metadata.values[keyword];
+                    self.metadata.insert(&format!("$P{}{}", n + offset, suffix), value);
+                }
+            }
+        }
+
+        self.data.extend(other.data.iter().map(|p| Parameter {
+            id: p.id.to_owned(),
+            events: p.events.clone()
+        }));
+        self.metadata.insert("$PAR", &self.data.len().to_string());
+        Ok(())
+    }
+}
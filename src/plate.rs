@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use crate::stats::median;
+use crate::FlowData;
+
+/// Events of all files acquired from one plate well
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WellSummary {
+    /// Names of the files of the well, in the order they were given
+    pub files: Vec<String>,
+    /// Total number of events over the files
+    pub events: usize,
+    /// Median of the pooled events of each parameter, keyed by $PnN
+    pub medians: HashMap<String, f64>
+}
+
+/// Summarize (file name, data) pairs per plate well, as given by $WELLID
+///
+/// Files without a $WELLID, or with a blank one, are left out. The events of each parameter
+/// are pooled over the files of a well before taking the median, so parameters missing from
+/// some of the files are summarized from the files that have them.
+pub fn summarize_wells(files: &[(String, FlowData)]) -> HashMap<String, WellSummary> {
+    let mut wells: HashMap<String, (WellSummary, HashMap<String, Vec<f64>>)> = HashMap::new();
+    for (name, flowdata) in files {
+        let well = match flowdata.metadata.values.get("$WELLID").map(|well| well.trim()) {
+            Some(well) if !well.is_empty() => well,
+            _ => continue
+        };
+
+        let (summary, pooled) = wells.entry(well.to_string()).or_default();
+        summary.files.push(name.to_owned());
+        summary.events += flowdata.data.first().map_or(0, |parameter| parameter.events.len());
+        for parameter in flowdata.data.iter() {
+            pooled.entry(parameter.id.to_owned()).or_default().extend_from_slice(&parameter.events);
+        }
+    }
+
+    wells.into_iter()
+        .map(|(well, (mut summary, pooled))| {
+            summary.medians = pooled.into_iter()
+                .map(|(id, events)| (id, median(&events)))
+                .collect();
+            (well, summary)
+        })
+        .collect()
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use crate::{FcsError, FlowData, Parameter};
+
+// tolerance for float data that is stored slightly below the range maximum
+const SATURATION_EPSILON: f64 = 1e-6;
+
+// upper bound on the number of intervals of time_bins, every interval holds a copy of the metadata
+const MAX_TIME_BINS: usize = 100_000;
+
+impl FlowData {
+    /// Count saturated events per parameter
+    ///
+    /// An event is saturated when its value is at (or within a small epsilon of) the maximum
+    /// value of the parameter's range, $PnR - 1. Values above the maximum are also counted.
+    /// Counts are keyed by parameter name ($PnN).
+    pub fn count_saturated(&self, params: &[&str]) -> Result<HashMap<String, usize>, FcsError> {
+        let mut counts = HashMap::new();
+        for &name in params {
+            let index = self.parameter_index(name)?;
+            let range: f64 = self.parameter_value(index, 'R')?;
+            let max = range - 1.0;
+
+            let saturated = self.data[index].events.iter()
+                .filter(|&&value| value >= max - SATURATION_EPSILON)
+                .count();
+            counts.insert(name.to_string(), saturated);
+        }
+
+        Ok(counts)
+    }
+
+    /// Split events into consecutive time intervals of `interval_seconds`
+    ///
+    /// Event times come from the time channel (the parameter named "Time", in any case) scaled
+    /// to seconds by $TIMESTEP, and intervals start at the earliest event. Every interval up to
+    /// the last event gets a FlowData, including intervals without events, with $TOT set to the
+    /// number of events in it. $TIMESTEP must be a positive number, and an interval splitting
+    /// the acquisition into more than 100000 intervals is a FcsError::InvalidArgument.
+    pub fn time_bins(&self, interval_seconds: f64) -> Result<Vec<FlowData>, FcsError> {
+        if !(interval_seconds > 0.0 && interval_seconds.is_finite()) {
+            return Err(FcsError::InvalidArgument(format!("time interval {} must be positive", interval_seconds)))
+        }
+
+        let time = &self.data[self.time_index()?].events;
+        let timestep = self.metadata.timestep()?
+            .ok_or_else(|| FcsError::MissingKeyword("$TIMESTEP".to_string()))?;
+        if !(timestep > 0.0 && timestep.is_finite()) {
+            return Err(FcsError::InvalidKeywordValue {
+                keyword: "$TIMESTEP".to_string(),
+                value: timestep.to_string()
+            })
+        }
+
+        let (start, end) = match (time.iter().copied().reduce(f64::min), time.iter().copied().reduce(f64::max)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(Vec::new())
+        };
+        let bin = |t: f64| ((t - start) * timestep / interval_seconds).floor();
+        // checked before allocating, a huge bin count would also saturate the usize cast
+        let last = bin(end);
+        if !last.is_finite() || last >= MAX_TIME_BINS as f64 {
+            return Err(FcsError::InvalidArgument(
+                format!("time interval {} splits the events into more than {} intervals", interval_seconds, MAX_TIME_BINS)
+            ))
+        }
+        let total_bins = last as usize + 1;
+        // NaN times fall in the first interval
+        let bins: Vec<usize> = time.iter().map(|&t| (bin(t) as usize).min(total_bins - 1)).collect();
+
+        let mut binned: Vec<Vec<Parameter>> = (0..total_bins)
+            .map(|_| self.data.iter().map(|p| Parameter { id: p.id.to_owned(), events: Vec::new() }).collect())
+            .collect();
+        for (i, parameter) in self.data.iter().enumerate() {
+            for (value, bin) in parameter.events.iter().zip(bins.iter()) {
+                binned[*bin][i].events.push(*value);
+            }
+        }
+
+        Ok(binned.into_iter()
+            .map(|data| {
+                let mut metadata = self.metadata.clone();
+                let total_events = data.first().map_or(0, |p| p.events.len());
+                metadata.insert("$TOT", &total_events.to_string());
+                FlowData { metadata, data }
+            })
+            .collect())
+    }
+
+    // Index of the time channel, the parameter named "Time" in any case
+    pub(crate) fn time_index(&self) -> Result<usize, FcsError> {
+        self.data.iter()
+            .position(|p| p.id.eq_ignore_ascii_case("time"))
+            .ok_or_else(|| FcsError::ParameterNotFound("Time".to_string()))
+    }
+}
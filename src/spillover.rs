@@ -0,0 +1,542 @@
+use crate::stats::median;
+use crate::{FcsError, FlowData, Metadata, Parameter};
+
+// keywords holding a spillover matrix, in order of preference
+const SPILLOVER_KEYWORDS: [&str; 3] = ["$SPILLOVER", "SPILL", "$SPILL"];
+
+/// Spillover matrix describing the fraction of each fluorophore's signal detected in every
+/// parameter. Row i holds the spillover of `parameters[i]` into each parameter, so the
+/// diagonal is 1 for a normalized matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spillover {
+    /// Parameter names ($PnN) in matrix order
+    pub parameters: Vec<String>,
+    /// Row-major matrix of n * n spillover coefficients
+    pub matrix: Vec<f64>
+}
+
+impl Spillover {
+    /// Identity matrix over the given parameters, a matrix without any spillover
+    pub fn identity(parameters: &[String]) -> Spillover {
+        let n = parameters.len();
+        let mut matrix = vec![0.0; n * n];
+        for i in 0..n {
+            matrix[i * n + i] = 1.0;
+        }
+
+        Spillover {
+            parameters: parameters.to_vec(),
+            matrix
+        }
+    }
+
+    /// Set the spillover coefficient of parameter `from` into parameter `to`
+    pub fn set(&mut self, from: &str, to: &str, coeff: f64) -> Result<(), FcsError> {
+        let index = |name: &str| {
+            self.parameters.iter()
+                .position(|p| p == name)
+                .ok_or_else(|| FcsError::ParameterNotFound(name.to_string()))
+        };
+        let row = index(from)?;
+        let col = index(to)?;
+        let n = self.parameters.len();
+        self.matrix[row * n + col] = coeff;
+        Ok(())
+    }
+
+    /// Spillover coefficient of parameter `from` into parameter `to`
+    pub fn coefficient(&self, from: &str, to: &str) -> Option<f64> {
+        let row = self.parameters.iter().position(|p| p == from)?;
+        let col = self.parameters.iter().position(|p| p == to)?;
+        Some(self.matrix[row * self.parameters.len() + col])
+    }
+
+    /// Format the matrix as a $SPILLOVER keyword value: the number of parameters, the
+    /// parameter names, and the row-major coefficients, all comma separated.
+    pub fn to_keyword_value(&self) -> String {
+        let mut fields = vec![self.parameters.len().to_string()];
+        fields.extend(self.parameters.iter().cloned());
+        fields.extend(self.matrix.iter().map(|c| c.to_string()));
+        fields.join(",")
+    }
+
+    /// Parse a $SPILLOVER keyword value, the inverse of `to_keyword_value`
+    pub fn from_keyword_value(value: &str) -> Result<Spillover, FcsError> {
+        let fields: Vec<&str> = value.split(',').map(|f| f.trim()).collect();
+        let n = fields.first()
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(|| FcsError::InvalidSpillover(format!("invalid parameter count in {:?}", value)))?;
+        let expected = n.checked_mul(n)
+            .and_then(|coefficients| coefficients.checked_add(n + 1))
+            .ok_or_else(|| FcsError::InvalidSpillover(format!("parameter count {} is too large", n)))?;
+        if fields.len() != expected {
+            return Err(FcsError::InvalidSpillover(
+                format!("expected {} fields for {} parameters, found {}", expected, n, fields.len())
+            ))
+        }
+
+        let parameters = fields[1..=n].iter().map(|p| p.to_string()).collect();
+        let matrix = fields[n+1..].iter()
+            .map(|c| c.parse::<f64>().map_err(|_| FcsError::InvalidSpillover(format!("invalid coefficient {:?}", c))))
+            .collect::<Result<Vec<f64>, FcsError>>()?;
+
+        Ok(Spillover {
+            parameters,
+            matrix
+        })
+    }
+
+    /// Check whether the matrix is the identity, meaning there is no spillover to correct
+    pub fn is_identity(&self) -> bool {
+        let n = self.parameters.len();
+        self.matrix.len() == n * n && self.matrix.iter().enumerate()
+            .all(|(i, &c)| c == if i / n == i % n { 1.0 } else { 0.0 })
+    }
+
+    /// Check that the matrix can be inverted for compensation
+    ///
+    /// Returns false when the matrix is not square, its determinant is within `tol` of zero, or
+    /// it is so ill-conditioned that the reciprocal of its 1-norm condition number is below
+    /// `tol`, as compensating with such a matrix amplifies noise by orders of magnitude.
+    pub fn is_invertible(&self, tol: f64) -> bool {
+        let n = self.parameters.len();
+        if self.matrix.len() != n * n || determinant(&self.matrix, n).abs() <= tol {
+            return false
+        }
+        match self.condition_number() {
+            Some(condition) => condition.recip() >= tol,
+            None => false
+        }
+    }
+
+    /// 1-norm condition number of the matrix, None if it is singular
+    pub fn condition_number(&self) -> Option<f64> {
+        let n = self.parameters.len();
+        let inverse = invert(&self.matrix, n)?;
+        Some(norm_1(&self.matrix, n) * norm_1(&inverse, n))
+    }
+}
+
+impl Metadata {
+    /// Store a spillover matrix in the $SPILLOVER keyword
+    pub fn set_spillover(&mut self, spillover: &Spillover) {
+        self.insert("$SPILLOVER", &spillover.to_keyword_value());
+    }
+
+    /// Spillover matrix of the data set, None when there is no $SPILLOVER (or SPILL) keyword
+    ///
+    /// The parameters of the matrix are matched back to the parameter names ($PnN) of the data
+    /// set, so the returned matrix can be used with `FlowData::compensate_with` directly.
+    /// Names are matched exactly, then ignoring ASCII case, and a label that is a plain number
+    /// n is taken as parameter n, as written by some software. A label matching no parameter
+    /// is a FcsError::ParameterNotFound.
+    pub fn spillover(&self) -> Option<Result<Spillover, FcsError>> {
+        let value = SPILLOVER_KEYWORDS.iter()
+            .find_map(|keyword| self.values.get(*keyword))?;
+        Some(Spillover::from_keyword_value(value).and_then(|mut spillover| {
+            for label in spillover.parameters.iter_mut() {
+                *label = self.parameter_name(label)?;
+            }
+            Ok(spillover)
+        }))
+    }
+
+    // Name ($PnN) of the parameter a spillover label refers to
+    fn parameter_name(&self, label: &str) -> Result<String, FcsError> {
+        let total_params = self.values.get("$PAR").and_then(|n| n.trim().parse::<usize>().ok()).unwrap_or(0);
+        let names: Vec<&str> = (1..=total_params)
+            .filter_map(|n| self.values.get(&format!("$P{}N", n)).map(|name| name.as_str()))
+            .collect();
+
+        names.iter().find(|name| **name == label)
+            .or_else(|| names.iter().find(|name| name.eq_ignore_ascii_case(label)))
+            .map(|name| name.to_string())
+            .or_else(|| {
+                let n = label.parse::<usize>().ok()?;
+                self.values.get(&format!("$P{}N", n)).filter(|_| n >= 1 && n <= total_params).cloned()
+            })
+            .ok_or_else(|| FcsError::ParameterNotFound(label.to_string()))
+    }
+
+    /// Best-effort check for data that is already compensated
+    ///
+    /// A spillover matrix ($SPILLOVER, or SPILL as written by some vendors) or FCS 3.0 $COMP
+    /// matrix equal to the identity is taken to mean the data was compensated before it was
+    /// stored, while any other matrix means it still has to be applied. Without a matrix, data
+    /// is considered compensated only when $ORIGINALITY is DataModified.
+    pub fn is_compensated(&self) -> bool {
+        for keyword in SPILLOVER_KEYWORDS {
+            if let Some(value) = self.values.get(keyword) {
+                return Spillover::from_keyword_value(value).is_ok_and(|s| s.is_identity())
+            }
+        }
+
+        // $COMP holds the matrix size followed by its coefficients, without parameter names
+        if let Some(value) = self.values.get("$COMP") {
+            let fields: Vec<f64> = value.split(',').filter_map(|f| f.trim().parse().ok()).collect();
+            return match fields.split_first() {
+                // a count that is too large for the matrix size is not a valid matrix
+                Some((&n, coefficients)) if (n as usize).checked_pow(2) == Some(coefficients.len()) => {
+                    let n = n as usize;
+                    coefficients.iter().enumerate().all(|(i, &c)| c == if i / n == i % n { 1.0 } else { 0.0 })
+                },
+                _ => false
+            }
+        }
+
+        self.values.get("$ORIGINALITY")
+            .is_some_and(|originality| originality.trim().eq_ignore_ascii_case("DataModified"))
+    }
+}
+
+/// Estimate a spillover matrix from single-stain controls
+///
+/// Each control is given as the name of the parameter ($PnN) detecting its fluorophore and the
+/// FlowData of the control. The spillover of a fluorophore into another parameter is the median,
+/// over events with a positive primary signal, of the ratio between the other parameter and the
+/// primary parameter. Every control must contain all of the control parameters.
+pub fn compute_spillover(controls: &[(String, FlowData)]) -> Result<Spillover, FcsError> {
+    if controls.is_empty() {
+        return Err(FcsError::InvalidSpillover("no single-stain controls given".to_string()))
+    }
+
+    let parameters: Vec<String> = controls.iter().map(|(name, _)| name.to_owned()).collect();
+    let n = parameters.len();
+    let mut matrix = vec![0.0; n * n];
+
+    for (row, (primary, control)) in controls.iter().enumerate() {
+        let find = |name: &str| {
+            control.data.iter()
+                .find(|p| p.id == name)
+                .ok_or_else(|| FcsError::ParameterNotFound(name.to_string()))
+        };
+        let primary_events = &find(primary)?.events;
+
+        for (col, name) in parameters.iter().enumerate() {
+            if col == row {
+                matrix[row * n + col] = 1.0;
+                continue
+            }
+
+            let events = &find(name)?.events;
+            let ratios: Vec<f64> = primary_events.iter()
+                .zip(events.iter())
+                .filter(|(p, _)| **p > 0.0)
+                .map(|(p, e)| e / p)
+                .collect();
+            if ratios.is_empty() {
+                return Err(FcsError::InvalidSpillover(
+                    format!("control for {} has no events with a positive signal", primary)
+                ))
+            }
+            matrix[row * n + col] = median(&ratios);
+        }
+    }
+
+    Ok(Spillover {
+        parameters,
+        matrix
+    })
+}
+
+/// Inverse of a spillover matrix, laid out to compensate events holding the values of every
+/// parameter of a data set.
+#[derive(Debug, Clone)]
+pub struct CompensationMatrix {
+    /// Index within an event of each spillover parameter, in spillover matrix order
+    pub columns: Vec<usize>,
+    /// Row-major inverse of the spillover matrix
+    pub inverse: Vec<f64>
+}
+
+impl CompensationMatrix {
+    /// Invert a spillover matrix for events whose values are ordered as `parameters` ($PnN).
+    /// Every fluorescence parameter of the spillover matrix must be one of `parameters`.
+    ///
+    /// Parameters of the matrix that neither spill into nor receive spill from any other
+    /// parameter are excluded before inverting; they pass through compensation unchanged. This
+    /// covers scatter parameters (named FSC/SSC), whatever their diagonal coefficient, and any
+    /// other parameter with an identity row and column. A scatter parameter with a non-zero
+    /// off-diagonal coefficient is compensated like any other parameter.
+    pub fn new(spillover: &Spillover, parameters: &[&str]) -> Result<CompensationMatrix, FcsError> {
+        let n = spillover.parameters.len();
+        if spillover.matrix.len() != n * n {
+            return Err(FcsError::InvalidSpillover(
+                format!("expected {} coefficients, found {}", n * n, spillover.matrix.len())
+            ))
+        }
+
+        let fluorescence: Vec<usize> = (0..n).filter(|&i| !is_scatter(spillover, i)).collect();
+        let columns = fluorescence.iter()
+            .map(|&i| {
+                let name = &spillover.parameters[i];
+                parameters.iter()
+                    .position(|p| p == name)
+                    .ok_or_else(|| FcsError::ParameterNotFound(name.to_string()))
+            })
+            .collect::<Result<Vec<usize>, FcsError>>()?;
+
+        let submatrix: Vec<f64> = fluorescence.iter()
+            .flat_map(|&row| fluorescence.iter().map(move |&col| spillover.matrix[row * n + col]))
+            .collect();
+        let inverse = invert(&submatrix, fluorescence.len())
+            .ok_or_else(|| FcsError::InvalidSpillover("matrix is singular".to_string()))?;
+
+        Ok(CompensationMatrix {
+            columns,
+            inverse
+        })
+    }
+
+    /// Compensate a single event in place. Parameters outside of the spillover matrix are
+    /// left unchanged.
+    ///
+    /// The event must hold a value for every parameter the matrix was built for. An event too
+    /// short to contain all of the spillover parameters is a FcsError::InvalidArgument and is
+    /// left unchanged.
+    pub fn apply(&self, event: &mut [f64]) -> Result<(), FcsError> {
+        let required = self.columns.iter().max().map_or(0, |&c| c + 1);
+        if event.len() < required {
+            return Err(FcsError::InvalidArgument(
+                format!("event holds {} values but the compensation matrix needs {}", event.len(), required)
+            ))
+        }
+
+        let n = self.columns.len();
+        let observed: Vec<f64> = self.columns.iter().map(|&c| event[c]).collect();
+        for (j, &column) in self.columns.iter().enumerate() {
+            event[column] = (0..n).map(|i| observed[i] * self.inverse[i * n + j]).sum();
+        }
+        Ok(())
+    }
+}
+
+/// Iterator adaptor compensating events on the fly
+///
+/// Wraps any iterator of events, each event holding the value of every parameter, and yields
+/// the compensated events one at a time so the whole data set never has to be held in memory.
+/// An event too short for the compensation matrix is yielded as an error, see
+/// `CompensationMatrix::apply`.
+pub struct CompensatedEventReader<I> {
+    events: I,
+    matrix: CompensationMatrix
+}
+
+impl<I: Iterator<Item = Vec<f64>>> CompensatedEventReader<I> {
+    pub fn new(events: I, matrix: CompensationMatrix) -> CompensatedEventReader<I> {
+        CompensatedEventReader {
+            events,
+            matrix
+        }
+    }
+}
+
+impl<I: Iterator<Item = Vec<f64>>> Iterator for CompensatedEventReader<I> {
+    type Item = Result<Vec<f64>, FcsError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = self.events.next()?;
+        Some(self.matrix.apply(&mut event).map(|_| event))
+    }
+}
+
+impl FlowData {
+    /// Compensate the event data in place with the spillover matrix of the metadata
+    ///
+    /// Same as `compensate_with` for the matrix returned by `Metadata::spillover`. The events
+    /// are modified in place, the metadata including $SPILLOVER is left as it is. Fails with
+    /// FcsError::MissingKeyword when the data set has no spillover matrix.
+    pub fn compensate(&mut self) -> Result<(), FcsError> {
+        let spillover = self.metadata.spillover()
+            .ok_or_else(|| FcsError::MissingKeyword("$SPILLOVER".to_string()))??;
+        self.compensate_with(&spillover)
+    }
+
+    /// Compensate the event data in place with the given spillover matrix
+    ///
+    /// The observed values of the spillover parameters are multiplied by the inverse of the
+    /// spillover matrix. Parameters not present in the matrix pass through unchanged.
+    pub fn compensate_with(&mut self, spillover: &Spillover) -> Result<(), FcsError> {
+        let names: Vec<&str> = self.data.iter().map(|p| p.id.as_str()).collect();
+        let matrix = CompensationMatrix::new(spillover, &names)?;
+        let total_events = self.event_count()?;
+
+        let n = matrix.columns.len();
+        let mut observed = vec![0.0; n];
+        for event in 0..total_events {
+            for (value, &column) in observed.iter_mut().zip(matrix.columns.iter()) {
+                *value = self.data[column].events[event];
+            }
+            for (j, &column) in matrix.columns.iter().enumerate() {
+                self.data[column].events[event] = (0..n).map(|i| observed[i] * matrix.inverse[i * n + j]).sum();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that compensation did not blow up the dynamic range of any parameter
+    ///
+    /// Compares every parameter with the parameter of the same name ($PnN) in `original`, the
+    /// data before compensation. A parameter is flagged when its largest absolute value grew by
+    /// more than `factor`, which usually means the matrix was transposed or applied to the
+    /// wrong parameters. Flagged parameters are returned in FcsError::SuspiciousCompensation.
+    pub fn compensation_sanity(&self, original: &FlowData, factor: f64) -> Result<(), FcsError> {
+        let abs_max = |events: &[f64]| events.iter().fold(0.0f64, |max, v| max.max(v.abs()));
+
+        let flagged: Vec<String> = self.data.iter()
+            .filter_map(|parameter| {
+                let before = original.data.iter().find(|p| p.id == parameter.id)?;
+                let (before, after) = (abs_max(&before.events), abs_max(&parameter.events));
+                (after > factor * before).then(|| parameter.id.to_owned())
+            })
+            .collect();
+
+        if flagged.is_empty() {
+            Ok(())
+        } else {
+            Err(FcsError::SuspiciousCompensation(flagged))
+        }
+    }
+}
+
+impl FlowData {
+    /// Spillover left over after compensating the data with `spillover`
+    ///
+    /// The data is compensated on a copy and, for every pair of spillover parameters, the
+    /// least-squares slope of parameter j against parameter i is estimated from the compensated
+    /// events. The result is laid out like the spillover matrix, row i and column j in
+    /// `spillover.parameters` order, with 1 on the diagonal. Off-diagonal values near zero mean
+    /// the matrix removed the spillover, values that stay away from zero point to under or over
+    /// compensation between that pair of parameters.
+    pub fn residual_spillover(&self, spillover: &Spillover) -> Result<Vec<Vec<f64>>, FcsError> {
+        let data = spillover.parameters.iter()
+            .map(|name| Ok(Parameter {
+                id: name.to_owned(),
+                events: self.data[self.parameter_index(name)?].events.clone()
+            }))
+            .collect::<Result<Vec<Parameter>, FcsError>>()?;
+        let mut compensated = FlowData { metadata: Metadata::default(), data };
+        compensated.compensate_with(spillover)?;
+
+        let total_events = compensated.event_count()?;
+        if total_events < 2 {
+            return Err(FcsError::InvalidArgument("at least two events are needed to estimate residual spillover".to_string()))
+        }
+        let means: Vec<f64> = compensated.data.iter()
+            .map(|p| p.events.iter().sum::<f64>() / total_events as f64)
+            .collect();
+        let covariance = |i: usize, j: usize| -> f64 {
+            compensated.data[i].events.iter().zip(compensated.data[j].events.iter())
+                .map(|(x, y)| (x - means[i]) * (y - means[j]))
+                .sum()
+        };
+
+        let n = compensated.data.len();
+        (0..n)
+            .map(|i| {
+                let variance = covariance(i, i);
+                if variance == 0.0 {
+                    return Err(FcsError::InvalidSpillover(format!("{} is constant after compensation", spillover.parameters[i])))
+                }
+                Ok((0..n).map(|j| if i == j { 1.0 } else { covariance(i, j) / variance }).collect())
+            })
+            .collect()
+    }
+}
+
+// Check whether the parameter at `index` of a spillover matrix can be left out of
+// compensation because it neither spills into nor receives spill from any other parameter.
+// Scatter channels (named FSC/SSC) only need zero off-diagonal coefficients, other parameters
+// also need a diagonal of 1.
+fn is_scatter(spillover: &Spillover, index: usize) -> bool {
+    let n = spillover.parameters.len();
+    let isolated = (0..n)
+        .filter(|&k| k != index)
+        .all(|k| spillover.matrix[index * n + k] == 0.0 && spillover.matrix[k * n + index] == 0.0);
+    if !isolated {
+        return false
+    }
+
+    let name = spillover.parameters[index].to_ascii_uppercase();
+    name.starts_with("FSC") || name.starts_with("SSC") || spillover.matrix[index * n + index] == 1.0
+}
+
+// Determinant of a row-major n * n matrix by Gaussian elimination with partial pivoting
+fn determinant(matrix: &[f64], n: usize) -> f64 {
+    let mut a = matrix.to_vec();
+    let mut det = 1.0;
+
+    for col in 0..n {
+        let pivot = match (col..n).max_by(|&x, &y| a[x * n + col].abs().total_cmp(&a[y * n + col].abs())) {
+            Some(pivot) => pivot,
+            None => return 0.0
+        };
+        if a[pivot * n + col] == 0.0 {
+            return 0.0
+        }
+        if pivot != col {
+            for k in 0..n {
+                a.swap(col * n + k, pivot * n + k);
+            }
+            det = -det;
+        }
+
+        det *= a[col * n + col];
+        for row in col+1..n {
+            let factor = a[row * n + col] / a[col * n + col];
+            for k in col..n {
+                a[row * n + k] -= factor * a[col * n + k];
+            }
+        }
+    }
+
+    det
+}
+
+// Maximum absolute column sum of a row-major n * n matrix
+fn norm_1(matrix: &[f64], n: usize) -> f64 {
+    (0..n)
+        .map(|col| (0..n).map(|row| matrix[row * n + col].abs()).sum::<f64>())
+        .fold(0.0, f64::max)
+}
+
+// Invert a row-major n * n matrix with Gauss-Jordan elimination and partial pivoting, None if
+// the matrix is singular
+pub(crate) fn invert(matrix: &[f64], n: usize) -> Option<Vec<f64>> {
+    let mut a = matrix.to_vec();
+    let mut inverse = vec![0.0; n * n];
+    for i in 0..n {
+        inverse[i * n + i] = 1.0;
+    }
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&x, &y| a[x * n + col].abs().total_cmp(&a[y * n + col].abs()))?;
+        if a[pivot * n + col].abs() < 1e-12 {
+            return None
+        }
+        for k in 0..n {
+            a.swap(col * n + k, pivot * n + k);
+            inverse.swap(col * n + k, pivot * n + k);
+        }
+
+        let scale = a[col * n + col];
+        for k in 0..n {
+            a[col * n + k] /= scale;
+            inverse[col * n + k] /= scale;
+        }
+
+        for row in 0..n {
+            let factor = a[row * n + col];
+            if row == col || factor == 0.0 {
+                continue
+            }
+            for k in 0..n {
+                a[row * n + k] -= factor * a[col * n + k];
+                inverse[row * n + k] -= factor * inverse[col * n + k];
+            }
+        }
+    }
+
+    Some(inverse)
+}
@@ -0,0 +1,139 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use crate::Parameter;
+
+/// Percentile commonly used for `Parameter::robust_max` when scaling plot axes
+pub const DEFAULT_ROBUST_MAX_PERCENTILE: f64 = 99.0;
+
+/// Float wrapper with a total order, equality, and hashing, so event values can be used as
+/// map keys. Zero and negative zero are the same key, and so are all NaNs, which order
+/// after every other value.
+#[derive(Debug, Clone, Copy)]
+pub struct OrderedFloat<T>(pub T);
+
+impl OrderedFloat<f64> {
+    // Bit pattern identifying the value, with every zero and every NaN mapped to one pattern
+    fn key(&self) -> u64 {
+        if self.0 == 0.0 {
+            0
+        } else if self.0.is_nan() {
+            f64::NAN.to_bits()
+        } else {
+            self.0.to_bits()
+        }
+    }
+}
+
+impl PartialEq for OrderedFloat<f64> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for OrderedFloat<f64> {}
+
+impl Hash for OrderedFloat<f64> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialOrd for OrderedFloat<f64> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedFloat<f64> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        f64::from_bits(self.key()).total_cmp(&f64::from_bits(other.key()))
+    }
+}
+
+impl Parameter {
+    /// Number of events of each distinct value, for discrete channels such as sort decisions
+    /// or cell subset ids
+    pub fn value_counts(&self) -> HashMap<OrderedFloat<f64>, usize> {
+        let mut counts = HashMap::new();
+        for value in self.events.iter() {
+            *counts.entry(OrderedFloat(*value)).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Quantile normalize the parameter events against a reference distribution
+    ///
+    /// Each event is replaced by the reference value at the same quantile as the event's rank,
+    /// interpolating linearly between reference values. Tied events share their average rank
+    /// and therefore map to the same value. The reference does not need to be sorted or have
+    /// the same length as the events. Nothing is changed if either is empty.
+    pub fn quantile_normalize(&mut self, reference: &[f64]) {
+        let n = self.events.len();
+        if n == 0 || reference.is_empty() {
+            return
+        }
+
+        let mut sorted_reference = reference.to_vec();
+        sorted_reference.sort_by(|a, b| a.total_cmp(b));
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| self.events[a].total_cmp(&self.events[b]));
+
+        let mut normalized = vec![0.0; n];
+        let mut start = 0;
+        while start < n {
+            // find the run of tied values starting at this rank
+            let mut end = start + 1;
+            while end < n && self.events[order[end]] == self.events[order[start]] {
+                end += 1;
+            }
+
+            let rank = (start + end - 1) as f64 / 2.0;
+            let q = if n > 1 { rank / (n - 1) as f64 } else { 0.5 };
+            let value = quantile(&sorted_reference, q);
+            for &index in order[start..end].iter() {
+                normalized[index] = value;
+            }
+            start = end;
+        }
+
+        self.events = normalized;
+    }
+
+    /// Median absolute deviation of the events from their median, NaN when there are no events
+    pub fn mad(&self) -> f64 {
+        let center = median(&self.events);
+        let deviations: Vec<f64> = self.events.iter().map(|value| (value - center).abs()).collect();
+        median(&deviations)
+    }
+
+    /// Value at `percentile` (0 to 100) of the events, interpolating linearly between events.
+    /// Used in place of the maximum to scale plot axes without being blown out by outliers,
+    /// typically with DEFAULT_ROBUST_MAX_PERCENTILE. NaN when there are no events.
+    pub fn robust_max(&self, percentile: f64) -> f64 {
+        let mut sorted = self.events.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        quantile(&sorted, percentile / 100.0)
+    }
+}
+
+// Linearly interpolated quantile of sorted values, q ranges from 0 to 1
+pub(crate) fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN
+    }
+
+    let position = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let fraction = position - lower as f64;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * fraction
+}
+
+// Median of unsorted values, NaN when empty
+pub(crate) fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    quantile(&sorted, 0.5)
+}
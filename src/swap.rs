@@ -0,0 +1,45 @@
+// Bulk byte swapping of fixed width values, used to decode data stored in the opposite byte
+// order of the host. With the `simd` feature, 32 and 64-bit values are swapped 16 bytes at a
+// time on x86_64 processors supporting SSSE3, otherwise value by value.
+
+// Reverse the bytes of every `width` byte value in place
+pub(crate) fn swap_bytes(bytes: &mut [u8], width: usize) {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if (width == 4 || width == 8) && is_x86_feature_detected!("ssse3") {
+        // SAFETY: SSSE3 support was checked above
+        unsafe { simd::swap_bytes(bytes, width) };
+        return
+    }
+
+    swap_bytes_scalar(bytes, width);
+}
+
+// Scalar fallback reversing one value at a time
+pub(crate) fn swap_bytes_scalar(bytes: &mut [u8], width: usize) {
+    for value in bytes.chunks_exact_mut(width) {
+        value.reverse();
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod simd {
+    use std::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_setr_epi8, _mm_shuffle_epi8, _mm_storeu_si128};
+
+    // Shuffle 16 byte blocks with a mask reversing each 4 or 8 byte lane, the remainder is
+    // swapped with the scalar fallback
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn swap_bytes(bytes: &mut [u8], width: usize) {
+        let mask = if width == 4 {
+            _mm_setr_epi8(3, 2, 1, 0, 7, 6, 5, 4, 11, 10, 9, 8, 15, 14, 13, 12)
+        } else {
+            _mm_setr_epi8(7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8)
+        };
+
+        let mut blocks = bytes.chunks_exact_mut(16);
+        for block in &mut blocks {
+            let ptr = block.as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(ptr, _mm_shuffle_epi8(_mm_loadu_si128(ptr), mask));
+        }
+        super::swap_bytes_scalar(blocks.into_remainder(), width);
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{FcsError, FlowData, Parameter};
+
+// Recommended scale of a parameter, applied to stored values in two steps. Log amplified
+// channel numbers ($PnE) are first converted to linear values, which are then mapped to the
+// display scale ($PnD). Values of a parameter with neither keyword are left as stored.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Scale {
+    // log amplification of the stored channel numbers: (decades, offset, range)
+    amplification: Option<(f64, f64, f64)>,
+    // lower bound of a log10 display, None for a linear display
+    log_display: Option<f64>
+}
+
+impl Scale {
+    // Whether the scale leaves values unchanged
+    pub(crate) fn is_linear(&self) -> bool {
+        self.amplification.is_none() && self.log_display.is_none()
+    }
+
+    // Convert a stored event value to a linear value
+    pub(crate) fn linearize(&self, value: f64) -> f64 {
+        match self.amplification {
+            Some((decades, offset, range)) => offset * 10f64.powf(decades * value / range),
+            None => value
+        }
+    }
+
+    // Convert a stored event value to this scale
+    pub(crate) fn apply(&self, value: f64) -> f64 {
+        let value = self.linearize(value);
+        match self.log_display {
+            Some(lower) => value.max(lower).log10(),
+            None => value
+        }
+    }
+}
+
+/// What to do with values a transform cannot map to a finite number, e.g. the log of zero
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NanPolicy {
+    /// Keep the NaN (or infinite) values
+    Propagate,
+    /// Replace them with the smallest finite transformed value of the parameter, or 0 when
+    /// there is none
+    ClampToMin,
+    /// Replace them with the given value
+    SetTo(f64)
+}
+
+impl NanPolicy {
+    // Replace the values that are not finite according to the policy
+    pub(crate) fn resolve(&self, values: &mut [f64]) {
+        let replacement = match *self {
+            NanPolicy::Propagate => return,
+            NanPolicy::ClampToMin => values.iter()
+                .copied()
+                .filter(|v| v.is_finite())
+                .fold(None, |min: Option<f64>, v| Some(min.map_or(v, |min| min.min(v))))
+                .unwrap_or(0.0),
+            NanPolicy::SetTo(value) => value
+        };
+        for value in values.iter_mut().filter(|v| !v.is_finite()) {
+            *value = replacement;
+        }
+    }
+}
+
+// Logicle scale of Parks et al. (2006), computed as described in Moore and Parks (2012),
+// "Update for the logicle data scale including operational code implementations"
+struct Logicle {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    f: f64,
+    x1: f64
+}
+
+impl Logicle {
+    fn new(t: f64, w: f64, m: f64, a: f64) -> Result<Logicle, FcsError> {
+        if t.is_nan() || m.is_nan() || t <= 0.0 || m <= 0.0 || w.is_nan() || w < 0.0 || a.is_nan() || a < 0.0 || 2.0 * w > m {
+            return Err(FcsError::InvalidArgument(format!(
+                "invalid logicle parameters T={}, W={}, M={}, A={}", t, w, m, a
+            )))
+        }
+
+        let w = w / (m + a);
+        let x2 = a / (m + a);
+        let x1 = x2 + w;
+        let x0 = x2 + 2.0 * w;
+        let b = (m + a) * std::f64::consts::LN_10;
+        let d = Logicle::solve_d(b, w);
+
+        let c_a = (x0 * (b + d)).exp();
+        let mf_a = (b * x1).exp() - c_a / (d * x1).exp();
+        let a = t / ((b.exp() - mf_a) - c_a / d.exp());
+
+        Ok(Logicle { a, b, c: c_a * a, d, f: -mf_a * a, x1 })
+    }
+
+    // Solve 2 * (ln(d) - ln(b)) + w * (b + d) = 0 for d by bisection, d = b when w is 0
+    fn solve_d(b: f64, w: f64) -> f64 {
+        if w == 0.0 {
+            return b
+        }
+        let g = |d: f64| 2.0 * (d.ln() - b.ln()) + w * (b + d);
+        let (mut low, mut high) = (0.0, b);
+        for _ in 0..200 {
+            let mid = (low + high) / 2.0;
+            if g(mid) > 0.0 { high = mid } else { low = mid }
+        }
+        (low + high) / 2.0
+    }
+
+    // Data value of the scale position y
+    fn biexponential(&self, y: f64) -> f64 {
+        self.a * (self.b * y).exp() - self.c * (-self.d * y).exp() + self.f
+    }
+
+    // Scale position of a data value, found with Newton steps kept inside a bisection bracket.
+    // The scale is symmetric around x1, where the data value is 0.
+    fn scale(&self, value: f64) -> f64 {
+        if !value.is_finite() {
+            return f64::NAN
+        }
+        if value < 0.0 {
+            return 2.0 * self.x1 - self.scale(-value)
+        }
+
+        let (mut low, mut high) = (self.x1, 1.0);
+        while self.biexponential(high) < value {
+            low = high;
+            high *= 2.0;
+        }
+
+        let mut y = (low + high) / 2.0;
+        for _ in 0..100 {
+            let delta = self.biexponential(y) - value;
+            if delta < 0.0 { low = y } else { high = y }
+            let slope = self.a * self.b * (self.b * y).exp() + self.c * self.d * (-self.d * y).exp();
+            let next = y - delta / slope;
+            let next = if next > low && next < high { next } else { (low + high) / 2.0 };
+            if (next - y).abs() <= f64::EPSILON * y.abs().max(1.0) {
+                return next
+            }
+            y = next;
+        }
+        y
+    }
+}
+
+impl Parameter {
+    /// Replace every event value with the result of `f` applied to it
+    pub fn map_events(&mut self, f: impl Fn(f64) -> f64) {
+        for value in self.events.iter_mut() {
+            *value = f(*value);
+        }
+    }
+
+    /// Copy of the parameter with every event value transformed to asinh(value / cofactor)
+    ///
+    /// Typical cofactors are 5 for mass cytometry and around 150 for fluorescence data.
+    /// Returns an InvalidArgument error when `cofactor` is not positive.
+    pub fn arcsinh(&self, cofactor: f64) -> Result<Parameter, FcsError> {
+        self.arcsinh_with_policy(cofactor, NanPolicy::Propagate)
+    }
+
+    /// Same as `arcsinh`, handling NaN and infinite results according to `policy`
+    pub fn arcsinh_with_policy(&self, cofactor: f64, policy: NanPolicy) -> Result<Parameter, FcsError> {
+        if cofactor.is_nan() || cofactor <= 0.0 {
+            return Err(FcsError::InvalidArgument(format!("arcsinh cofactor must be positive, got {}", cofactor)))
+        }
+        let mut events: Vec<f64> = self.events.iter().map(|value| (value / cofactor).asinh()).collect();
+        policy.resolve(&mut events);
+        Ok(Parameter {
+            id: self.id.clone(),
+            events
+        })
+    }
+
+    /// Copy of the parameter with every event value transformed to its logicle scale position
+    ///
+    /// `t` is the top of the data range, `w` the width of the linear region in decades, `m`
+    /// the number of decades of the full scale, and `a` additional negative decades. Values
+    /// map to [0, 1] for data between the bottom of the scale and `t`; 0 maps to
+    /// (w + a) / (m + a). Returns an InvalidArgument error when t or m is not positive, w or
+    /// a is negative, or 2w exceeds m. NaN and infinite values map to NaN.
+    pub fn logicle(&self, t: f64, w: f64, m: f64, a: f64) -> Result<Parameter, FcsError> {
+        self.logicle_with_policy(t, w, m, a, NanPolicy::Propagate)
+    }
+
+    /// Same as `logicle`, handling the NaN results of NaN and infinite values according to
+    /// `policy`
+    pub fn logicle_with_policy(&self, t: f64, w: f64, m: f64, a: f64, policy: NanPolicy) -> Result<Parameter, FcsError> {
+        let logicle = Logicle::new(t, w, m, a)?;
+        let mut events: Vec<f64> = self.events.iter().map(|value| logicle.scale(*value)).collect();
+        policy.resolve(&mut events);
+        Ok(Parameter {
+            id: self.id.clone(),
+            events
+        })
+    }
+}
+
+impl FlowData {
+    /// Apply `f` to every event value of the parameter named `id` ($PnN)
+    pub fn map_parameter(&mut self, id: &str, f: impl Fn(f64) -> f64) -> Result<(), FcsError> {
+        self.map_parameter_with_policy(id, f, NanPolicy::Propagate)
+    }
+
+    /// Same as `map_parameter`, then handle the values that are not finite according to
+    /// `policy`
+    pub fn map_parameter_with_policy(&mut self, id: &str, f: impl Fn(f64) -> f64, policy: NanPolicy) -> Result<(), FcsError> {
+        let index = self.parameter_index(id)?;
+        let parameter = &mut self.data[index];
+        parameter.map_events(f);
+        policy.resolve(&mut parameter.events);
+        Ok(())
+    }
+
+    /// Convert the parameter named `id` ($PnN) to log10 values
+    ///
+    /// Zero and negative values have no logarithm and become NaN, which is then handled
+    /// according to `policy`.
+    pub fn log_transform(&mut self, id: &str, policy: NanPolicy) -> Result<(), FcsError> {
+        self.map_parameter_with_policy(id, |value| if value > 0.0 { value.log10() } else { f64::NAN }, policy)
+    }
+
+    /// Apply the scaling recommended by the file to every parameter
+    ///
+    /// Parameters with log amplification in $PnE ("decades,offset" with non-zero decades) are
+    /// first converted from channel numbers to linear values with
+    /// offset * 10^(decades * value / $PnR), like `linearize` does. Parameters with a
+    /// "Logarithmic,decades,offset" $PnD display keyword (FCS 3.1) are then converted to log10
+    /// values, clamping values below the offset to the offset. Parameters without either
+    /// keyword, or with linear ones, are left untouched.
+    ///
+    /// Like `linearize`, the $PnE of scaled parameters is set to "0,0" and their logarithmic
+    /// $PnD is removed, so calling this again leaves them untouched.
+    pub fn apply_recommended_scaling(&mut self) -> Result<(), FcsError> {
+        self.apply_recommended_scaling_with_policy(NanPolicy::Propagate)
+    }
+
+    /// Same as `apply_recommended_scaling`, handling the values of every scaled parameter
+    /// that are not finite according to `policy`, e.g. the log of a value at or below a
+    /// $PnD offset of 0
+    pub fn apply_recommended_scaling_with_policy(&mut self, policy: NanPolicy) -> Result<(), FcsError> {
+        let scales = (0..self.data.len())
+            .map(|i| self.recommended_scale(i))
+            .collect::<Result<Vec<Scale>, FcsError>>()?;
+
+        for (index, scale) in scales.into_iter().enumerate() {
+            if scale.is_linear() {
+                continue
+            }
+            let parameter = &mut self.data[index];
+            parameter.map_events(|value| scale.apply(value));
+            policy.resolve(&mut parameter.events);
+
+            if scale.amplification.is_some() {
+                self.metadata.insert(&format!("$P{}E", index+1), "0,0");
+            }
+            if scale.log_display.is_some() {
+                let keyword = format!("$P{}D", index+1);
+                self.metadata.values.remove(&keyword);
+                self.metadata.keywords.retain(|k| *k != keyword);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert log amplified parameters from stored channel numbers to linear values
+    ///
+    /// Parameters whose $PnE is "decades,offset" with non-zero decades are converted with
+    /// offset * 10^(decades * value / $PnR), and their $PnE is set to "0,0" so that calling
+    /// this again leaves them untouched. Unlike apply_recommended_scaling, $PnD is ignored.
+    pub fn linearize(&mut self) -> Result<(), FcsError> {
+        for index in 0..self.data.len() {
+            if let Some((decades, offset)) = self.log_amplification(index)? {
+                let range: f64 = self.parameter_value(index, 'R')?;
+                let scale = Scale { amplification: Some((decades, offset, range)), log_display: None };
+                self.data[index].map_events(|value| scale.linearize(value));
+                self.metadata.insert(&format!("$P{}E", index+1), "0,0");
+            }
+        }
+        Ok(())
+    }
+
+    // Work out the recommended scale of the parameter at `index` from $PnE and $PnD
+    pub(crate) fn recommended_scale(&self, index: usize) -> Result<Scale, FcsError> {
+        let amplification = match self.log_amplification(index)? {
+            Some((decades, offset)) => Some((decades, offset, self.parameter_value(index, 'R')?)),
+            None => None
+        };
+
+        let display_keyword = format!("$P{}D", index+1);
+        let log_display = match self.metadata.values.get(&display_keyword) {
+            Some(display) => {
+                let fields: Vec<&str> = display.split(',').map(|f| f.trim()).collect();
+                let invalid = || FcsError::InvalidKeywordValue {
+                    keyword: display_keyword.to_owned(),
+                    value: display.to_string()
+                };
+
+                match fields.as_slice() {
+                    [kind, _, _] if kind.eq_ignore_ascii_case("Linear") => None,
+                    [kind, _, offset] if kind.eq_ignore_ascii_case("Logarithmic") => {
+                        Some(offset.parse::<f64>().map_err(|_| invalid())?)
+                    },
+                    _ => return Err(invalid())
+                }
+            },
+            None => None
+        };
+
+        Ok(Scale { amplification, log_display })
+    }
+
+    // Parse $PnE into (decades, offset), None for linear amplification. A zero offset with
+    // non-zero decades is treated as an offset of 1, as written by older FCS 2.0/3.0 software.
+    pub(crate) fn log_amplification(&self, index: usize) -> Result<Option<(f64, f64)>, FcsError> {
+        let keyword = format!("$P{}E", index+1);
+        let value = match self.metadata.values.get(&keyword) {
+            Some(value) => value,
+            None => return Ok(None)
+        };
+        let invalid = || FcsError::InvalidKeywordValue {
+            keyword: keyword.to_owned(),
+            value: value.to_string()
+        };
+
+        let fields: Vec<f64> = value.split(',')
+            .map(|f| f.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        match fields.as_slice() {
+            [decades, _] if *decades == 0.0 => Ok(None),
+            [decades, offset] if *offset == 0.0 => Ok(Some((*decades, 1.0))),
+            [decades, offset] => Ok(Some((*decades, *offset))),
+            _ => Err(invalid())
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::LazyLock;
+use regex::Regex;
+use crate::{FcsError, FlowData, OPTIONAL_KEYWORDS_3_2};
+
+// Supported versions, oldest first
+const VERSIONS: [&str; 4] = ["FCS2.0", "FCS3.0", "FCS3.1", "FCS3.2"];
+
+// FCS 3.1 introduced $PnD and 3.2 the remaining parameter keywords
+static DISPLAY_KEYWORD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\$P\d+D$").unwrap());
+static PARAMETER_KEYWORD_3_2: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\$P\d+(DATATYPE|TYPE|DET|TAG|ANALYTE|FEATURE|CALIBRATION)$").unwrap()
+});
+
+// Segment offset keywords introduced by FCS 3.0, FCS 2.0 only stores the offsets in the header
+const SEGMENT_KEYWORDS: [&str; 6] = [
+    "$BEGINANALYSIS", "$ENDANALYSIS", "$BEGINSTEXT", "$ENDSTEXT", "$BEGINDATA", "$ENDDATA"
+];
+
+impl FlowData {
+    /// Convert the metadata to another FCS version, e.g. "FCS3.0", before writing
+    ///
+    /// Keywords required by the target version are added and keywords it does not know are
+    /// removed, so the result passes `validate_metadata`. From FCS 3.0 on, missing segment
+    /// offsets ($BEGINDATA, $ENDDATA, $BEGINANALYSIS, $ENDANALYSIS, $BEGINSTEXT and $ENDSTEXT)
+    /// are taken from the header, and $PnE "0,0" (linear amplification) and $MODE "L" are
+    /// added. FCS 2.0 keeps the segment offsets in the header only, so the offset keywords
+    /// are removed. Before FCS 3.1, $BYTEORD lists every byte of a value, so double data gets
+    /// an 8 byte order. From 3.1 on it is reduced to 1,2,3,4 or 4,3,2,1 for every data type,
+    /// so a mixed $BYTEORD such as 3,4,1,2 gives an UnsupportedByteOrder error. Keywords
+    /// introduced after the target version, such as $PnD (3.1) or $PnDATATYPE and $FLOWRATE
+    /// (3.2), cannot be carried over and give an IncompatibleKeyword error. On error the data
+    /// set is left unchanged.
+    pub fn convert_version(&mut self, target: &str) -> Result<(), FcsError> {
+        let target_index = VERSIONS.iter()
+            .position(|version| *version == target)
+            .ok_or_else(|| FcsError::UnsupportedVersion(target.to_string()))?;
+
+        if let Some(keyword) = self.metadata.keywords.iter().find(|keyword| {
+            (target_index < 2 && DISPLAY_KEYWORD.is_match(keyword))
+                || (target_index < 3 && (PARAMETER_KEYWORD_3_2.is_match(keyword) || OPTIONAL_KEYWORDS_3_2.contains(&keyword.as_str())))
+        }) {
+            return Err(FcsError::IncompatibleKeyword {
+                keyword: keyword.to_owned(),
+                version: target.to_string()
+            })
+        }
+
+        let doubles = self.metadata.values.get("$DATATYPE").map(|t| t.trim()) == Some("D");
+        let byte_order = match self.metadata.values.get("$BYTEORD") {
+            Some(byte_order) => convert_byte_order(byte_order, doubles, target_index)?,
+            None => None
+        };
+
+        if target_index >= 1 {
+            let header = &self.metadata.header;
+            let offsets = [
+                header.analysis_start, header.analysis_end, 0, 0, header.data_start, header.data_end
+            ];
+            for (keyword, offset) in SEGMENT_KEYWORDS.iter().zip(offsets) {
+                if !self.metadata.values.contains_key(*keyword) {
+                    self.metadata.insert(keyword, &offset.to_string());
+                }
+            }
+            for n in 1..=self.data.len() {
+                let keyword = format!("$P{}E", n);
+                if !self.metadata.values.contains_key(&keyword) {
+                    self.metadata.insert(&keyword, "0,0");
+                }
+            }
+            if !self.metadata.values.contains_key("$MODE") {
+                self.metadata.insert("$MODE", "L");
+            }
+        } else {
+            for keyword in SEGMENT_KEYWORDS {
+                self.metadata.values.remove(keyword);
+            }
+            self.metadata.keywords.retain(|keyword| !SEGMENT_KEYWORDS.contains(&keyword.as_str()));
+        }
+
+        if let Some(byte_order) = byte_order {
+            self.metadata.insert("$BYTEORD", &byte_order);
+        }
+        self.metadata.version = target.to_string();
+        self.metadata.header.version = target.to_string();
+        Ok(())
+    }
+}
+
+// $BYTEORD for the version at `target_index`, None when the current one can be kept. Before
+// FCS 3.1 the order lists every byte of a value and may be mixed, from 3.1 on it only gives the
+// endianness of 4 bytes.
+fn convert_byte_order(byte_order: &str, doubles: bool, target_index: usize) -> Result<Option<String>, FcsError> {
+    let parsed: Option<Vec<usize>> = byte_order.split(',').map(|b| b.trim().parse().ok()).collect();
+    let big_endian = match parsed {
+        Some(order) if is_plain_order(order.iter()) => false,
+        Some(order) if is_plain_order(order.iter().rev()) => true,
+        // a mixed order has no equivalent in 3.1, nor for the 8 bytes of a double
+        _ if target_index >= 2 || doubles => return Err(FcsError::unsupported_byte_order(byte_order)),
+        _ => return Ok(None)
+    };
+
+    let width = match (target_index, doubles) {
+        (2.., _) => 4,
+        (_, true) => 8,
+        _ => return Ok(None)
+    };
+    let mut order: Vec<String> = (1..=width).map(|b| b.to_string()).collect();
+    if big_endian {
+        order.reverse();
+    }
+    Ok(Some(order.join(",")))
+}
+
+// Whether the byte positions run 1, 2, 3... in the given direction
+fn is_plain_order<'a>(order: impl Iterator<Item = &'a usize>) -> bool {
+    order.enumerate().all(|(i, b)| *b == i + 1)
+}
@@ -0,0 +1,469 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::{File, OpenOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+#[cfg(not(target_arch = "wasm32"))]
+use byteorder::ReadBytesExt;
+use byteorder::{WriteBytesExt, LittleEndian, BigEndian};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{clean_kv, read_header, read_raw_kv};
+use crate::{FlowData, FcsError};
+
+// the header is always 58 bytes long, so the text segment starts right after it
+const TEXT_START: u64 = 58;
+
+// version written for metadata without one
+const DEFAULT_VERSION: &str = "FCS3.1";
+
+// header offset fields are 8 bytes wide, larger offsets are only stored in the text segment
+const MAX_HEADER_OFFSET: u64 = 99_999_999;
+
+// keywords holding the offsets of segments, which change the layout of the file
+#[cfg(not(target_arch = "wasm32"))]
+const SEGMENT_KEYWORDS: [&str; 7] = [
+    "$BEGINDATA", "$ENDDATA", "$NEXTDATA", "$BEGINSTEXT", "$ENDSTEXT", "$BEGINANALYSIS", "$ENDANALYSIS"
+];
+
+// keywords describing how events are stored in the data segment, along with every $PnB
+#[cfg(not(target_arch = "wasm32"))]
+const LAYOUT_KEYWORDS: [&str; 4] = ["$TOT", "$PAR", "$DATATYPE", "$BYTEORD"];
+
+/// Options for writing FCS files.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Delimitter of the text segment, the delimitter of the metadata when None. Delimitters
+    /// inside keywords and values are escaped by doubling them.
+    pub delimitter: Option<u8>
+}
+
+/// Write FCS files
+///
+/// This function serializes a FlowData struct to an fcs file. The header and the
+/// segment offset keywords ($BEGINDATA, $ENDDATA, etc.) are recomputed from the
+/// written segments, and $PAR/$TOT are taken from the parameter data. Event data is
+/// encoded according to the $DATATYPE and $BYTEORD keywords of the metadata. The header
+/// keeps the version of the metadata, or FCS3.1 when the metadata has none.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_fcs(flowdata: &FlowData, filename: &str) -> Result<(), FcsError> {
+    write_fcs_with_options(flowdata, filename, &WriteOptions::default())
+}
+
+/// Write FCS files with the given options
+///
+/// Same as `write_fcs`, with the text segment delimitter taken from `opts`. The delimitter
+/// must be an ASCII character other than NUL and DEL, as required by the standard.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write_fcs_with_options(flowdata: &FlowData, filename: &str, opts: &WriteOptions) -> Result<(), FcsError> {
+    let delimitter = opts.delimitter.unwrap_or(flowdata.metadata.delimitter);
+    if !(1..=126).contains(&delimitter) {
+        return Err(FcsError::InvalidArgument(format!("delimitter {:#04x} is not an ASCII character from 1 to 126", delimitter)))
+    }
+
+    let data = encode_data(flowdata)?;
+    let (header, text) = build_header_and_text(flowdata, delimitter, data.len() as u64)?;
+
+    let file = File::create(filename)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&header)?;
+    writer.write_all(&text)?;
+    writer.write_all(&data)?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Update keyword values of an existing fcs file in place
+///
+/// Only the primary text segment is rewritten, the header, the supplemental text segment and
+/// the data segment are left untouched. Keywords and values that are not updated are written
+/// back byte for byte as stored, and keywords not yet in the file are appended. The updated
+/// text segment must fit in the span of the original one, a shorter segment is padded with
+/// spaces at the end of its last value. When it does not fit, FcsError::TextSegmentTooLarge is
+/// returned and the whole file has to be rewritten with `write_fcs`.
+///
+/// Keywords are matched ignoring case, as keywords are case insensitive. Keywords giving the
+/// offsets of the other segments ($BEGINDATA, $ENDDATA, $NEXTDATA, $BEGINSTEXT, $ENDSTEXT,
+/// $BEGINANALYSIS and $ENDANALYSIS) or how the data segment is stored ($TOT, $PAR, $DATATYPE,
+/// $BYTEORD and $PnB) describe the layout of the file and cannot be updated, they are rejected
+/// with FcsError::InvalidArgument.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn update_keywords_in_place(filename: &str, updates: &HashMap<String, String>) -> Result<(), FcsError> {
+    if let Some(keyword) = updates.keys().find(|k| is_layout_keyword(k)) {
+        return Err(FcsError::InvalidArgument(format!("{} describes the layout of the file and cannot be updated in place", keyword)))
+    }
+
+    let file = File::open(filename)?;
+    let mut reader = BufReader::new(file);
+    let header = read_header(&mut reader)?;
+    reader.seek(SeekFrom::Start(header.txt_start))?;
+    let delimitter = reader.read_u8()?;
+
+    // pairs of the primary text segment as stored, only escaped delimitters are collapsed
+    let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    while reader.stream_position()? < header.txt_end {
+        let (keyword, value) = read_raw_kv(&mut reader, delimitter, header.txt_end)?;
+        // padding after the last pair reads as a blank keyword
+        if !clean_kv(&keyword, &[]).0.is_empty() {
+            pairs.push((keyword, value));
+        }
+    }
+
+    // keep the order of the updates stable for keywords that have to be appended
+    let mut keywords: Vec<&String> = updates.keys().collect();
+    keywords.sort();
+    for keyword in keywords {
+        let value = updates[keyword].as_bytes().to_vec();
+        match pairs.iter_mut().find(|(k, _)| clean_kv(k, &[]).0.eq_ignore_ascii_case(keyword.trim())) {
+            Some((_, stored)) => *stored = value,
+            None => pairs.push((keyword.as_bytes().to_vec(), value))
+        }
+    }
+
+    let mut text = vec![delimitter];
+    for (keyword, value) in pairs.iter() {
+        push_raw_kv(&mut text, delimitter, keyword, value)?;
+    }
+
+    let available = header.txt_end - header.txt_start + 1;
+    let required = text.len() as u64;
+    if required > available {
+        return Err(FcsError::TextSegmentTooLarge { required, available })
+    }
+    // values are trimmed when read, so trailing spaces in the last value are dropped
+    let closing = text.pop();
+    text.resize((available - 1) as usize, b' ');
+    text.extend(closing);
+
+    let mut file = OpenOptions::new().write(true).open(filename)?;
+    file.seek(SeekFrom::Start(header.txt_start))?;
+    file.write_all(&text)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+// Whether updating the keyword would change the layout of the file
+#[cfg(not(target_arch = "wasm32"))]
+fn is_layout_keyword(keyword: &str) -> bool {
+    let keyword = keyword.trim().to_ascii_uppercase();
+    let bit_width = keyword.strip_prefix("$P")
+        .and_then(|rest| rest.strip_suffix('B'))
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+    bit_width || SEGMENT_KEYWORDS.contains(&keyword.as_str()) || LAYOUT_KEYWORDS.contains(&keyword.as_str())
+}
+
+/// Type of the values stored in a data segment ($DATATYPE)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    /// Unsigned binary integers ("I") of the width given by $PnB
+    Integer,
+    /// Single precision floats ("F")
+    Float,
+    /// Double precision floats ("D")
+    Double,
+    /// ASCII encoded numbers ("A")
+    Ascii
+}
+
+impl DataType {
+    /// Parse a $DATATYPE keyword value
+    pub fn from_keyword_value(value: &str) -> Result<DataType, FcsError> {
+        match value.trim() {
+            "I" => Ok(DataType::Integer),
+            "F" => Ok(DataType::Float),
+            "D" => Ok(DataType::Double),
+            "A" => Ok(DataType::Ascii),
+            other => Err(FcsError::UnsupportedDataType(other.to_string()))
+        }
+    }
+
+    /// The $DATATYPE keyword value of the data type
+    pub fn keyword_value(&self) -> &'static str {
+        match self {
+            DataType::Integer => "I",
+            DataType::Float => "F",
+            DataType::Double => "D",
+            DataType::Ascii => "A"
+        }
+    }
+}
+
+impl FlowData {
+    /// Encode the events as a row-major byte buffer, the layout of an FCS data segment
+    ///
+    /// Events are written one after another, each holding a value of every parameter in $PnN
+    /// order, with the given data type and $BYTEORD (little "1,2,3,4" or big "4,3,2,1"
+    /// endian). Integers take the width of their $PnB keyword and are truncated to it, floats
+    /// take 32 bits, or 64 bits when their $PnB is 64 as read by `read_fcs`, and doubles take
+    /// 64 bits. ASCII data cannot be encoded.
+    pub fn to_interleaved_bytes(&self, data_type: DataType, byte_order: &str) -> Result<Vec<u8>, FcsError> {
+        let little_endian = is_little_endian(byte_order)?;
+        let total_events = self.event_count()?;
+        let bit_widths = bit_widths(self, data_type)?;
+
+        let record_bytes: usize = bit_widths.iter().map(|bits| bits / 8).sum();
+        let mut data: Vec<u8> = Vec::with_capacity(record_bytes * total_events);
+
+        for j in 0..total_events {
+            for (parameter, bits) in self.data.iter().zip(bit_widths.iter()) {
+                let value = parameter.events[j];
+                match (data_type, *bits, little_endian) {
+                    (DataType::Integer, 8, _) => data.write_u8(value as u8)?,
+                    (DataType::Integer, 16, true) => data.write_u16::<LittleEndian>(value as u16)?,
+                    (DataType::Integer, 16, false) => data.write_u16::<BigEndian>(value as u16)?,
+                    (DataType::Integer, 32, true) => data.write_u32::<LittleEndian>(value as u32)?,
+                    (DataType::Integer, 32, false) => data.write_u32::<BigEndian>(value as u32)?,
+                    (DataType::Integer, 64, true) => data.write_u64::<LittleEndian>(value as u64)?,
+                    (DataType::Integer, 64, false) => data.write_u64::<BigEndian>(value as u64)?,
+                    (DataType::Float, 64, true) => data.write_f64::<LittleEndian>(value)?,
+                    (DataType::Float, 64, false) => data.write_f64::<BigEndian>(value)?,
+                    (DataType::Float, _, true) => data.write_f32::<LittleEndian>(value as f32)?,
+                    (DataType::Float, _, false) => data.write_f32::<BigEndian>(value as f32)?,
+                    (DataType::Double, _, true) => data.write_f64::<LittleEndian>(value)?,
+                    (DataType::Double, _, false) => data.write_f64::<BigEndian>(value)?,
+                    _ => unreachable!("bit widths are validated above")
+                }
+            }
+        }
+
+        Ok(data)
+    }
+
+    /// Stable 64-bit digest of the event values
+    ///
+    /// The digest is a FNV-1a hash of the bits of every event value, parameter by parameter,
+    /// including the number of events of each parameter. It does not depend on the platform or
+    /// the Rust version, so digests can be stored and compared across runs, e.g. to check that
+    /// a write and read round trip preserves the data. Negative zero hashes like zero and all
+    /// NaNs hash alike, parameter names and metadata are not part of the digest.
+    pub fn data_digest(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        let mut hash = FNV_OFFSET;
+        let mut update = |word: u64| {
+            for byte in word.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        };
+
+        for parameter in self.data.iter() {
+            update(parameter.events.len() as u64);
+            for value in parameter.events.iter() {
+                let bits = if *value == 0.0 {
+                    0
+                } else if value.is_nan() {
+                    f64::NAN.to_bits()
+                } else {
+                    value.to_bits()
+                };
+                update(bits);
+            }
+        }
+        hash
+    }
+
+    /// Recompute the data segment offsets after events or parameters were edited
+    ///
+    /// $BEGINDATA and $ENDDATA are set to where `write_fcs` places the data segment for the
+    /// current events, sized from $DATATYPE and $PnB, and $PAR/$TOT are set from the data. The
+    /// text and data offsets of the header are updated to match, with data offsets that do not
+    /// fit in the header set to 0 as `write_fcs` does.
+    pub fn recompute_data_offsets(&mut self) -> Result<(), FcsError> {
+        let data_len = self.event_count()? as u64 * record_bytes(self)? as u64;
+        let (_, text) = build_header_and_text(self, self.metadata.delimitter, data_len)?;
+        let data_start = TEXT_START + text.len() as u64;
+        let data_end = if data_len == 0 { data_start } else { data_start + data_len - 1 };
+
+        let header = &mut self.metadata.header;
+        header.txt_start = TEXT_START;
+        header.txt_end = data_start - 1;
+        (header.data_start, header.data_end) = if data_end > MAX_HEADER_OFFSET {
+            (0, 0)
+        } else {
+            (data_start, data_end)
+        };
+
+        let total_events = self.event_count()?;
+        let total_params = self.data.len();
+        let metadata = &mut self.metadata;
+        metadata.insert("$BEGINDATA", &data_start.to_string());
+        metadata.insert("$ENDDATA", &data_end.to_string());
+        metadata.insert("$PAR", &total_params.to_string());
+        metadata.insert("$TOT", &total_events.to_string());
+        Ok(())
+    }
+}
+
+// Build header and text segment bytes. The offset keywords are part of the text segment, so its
+// length depends on the offsets it contains; iterate until the data offsets stop moving.
+fn build_header_and_text(flowdata: &FlowData, delimitter: u8, data_len: u64) -> Result<(Vec<u8>, Vec<u8>), FcsError> {
+    let total_events = flowdata.event_count()?;
+    let mut data_start = 0u64;
+
+    loop {
+        let data_end = if data_len == 0 { data_start } else { data_start + data_len - 1 };
+        let text = build_text(flowdata, delimitter, total_events, data_start, data_end)?;
+        let txt_end = TEXT_START + text.len() as u64 - 1;
+
+        if txt_end + 1 == data_start {
+            // metadata built from scratch has no version yet, write it as the latest supported
+            let version = match flowdata.metadata.version.as_str() {
+                "" => DEFAULT_VERSION,
+                version => version
+            };
+            let header = build_header(version, txt_end, data_start, data_end);
+            return Ok((header, text))
+        }
+        data_start = txt_end + 1;
+    }
+}
+
+// Build the text segment, overriding structural keywords with values matching the written file
+fn build_text(flowdata: &FlowData, delimitter: u8, total_events: usize, data_start: u64, data_end: u64) -> Result<Vec<u8>, FcsError> {
+    let overrides = [
+        ("$BEGINANALYSIS", "0".to_string()),
+        ("$ENDANALYSIS", "0".to_string()),
+        ("$BEGINSTEXT", "0".to_string()),
+        ("$ENDSTEXT", "0".to_string()),
+        ("$BEGINDATA", data_start.to_string()),
+        ("$ENDDATA", data_end.to_string()),
+        ("$NEXTDATA", "0".to_string()),
+        ("$PAR", flowdata.data.len().to_string()),
+        ("$TOT", total_events.to_string()),
+    ];
+    let metadata = &flowdata.metadata;
+
+    let mut text = vec![delimitter];
+    for keyword in metadata.keywords.iter() {
+        let value = match overrides.iter().find(|(k, _)| k == keyword) {
+            Some((_, value)) => value.as_str(),
+            None => metadata.values.get(keyword).map(|v| v.as_str()).unwrap_or("")
+        };
+        push_kv(&mut text, delimitter, keyword, value)?;
+    }
+
+    // structural keywords missing from the metadata still need to be written, except for the
+    // segment offsets of FCS 2.0, which only has them in the header unless they do not fit
+    let header_offsets = metadata.version == "FCS2.0" && data_end <= MAX_HEADER_OFFSET;
+    for (keyword, value) in overrides.iter() {
+        if header_offsets && !matches!(*keyword, "$NEXTDATA" | "$PAR" | "$TOT") {
+            continue
+        }
+        if !metadata.keywords.iter().any(|k| k == keyword) {
+            push_kv(&mut text, delimitter, keyword, value)?;
+        }
+    }
+
+    Ok(text)
+}
+
+// Append a keyword and value to the text segment, escaping delimitters by doubling them
+fn push_kv(text: &mut Vec<u8>, delimitter: u8, keyword: &str, value: &str) -> Result<(), FcsError> {
+    push_raw_kv(text, delimitter, keyword.as_bytes(), value.as_bytes())
+}
+
+// Append the bytes of a keyword and value to the text segment, escaping delimitters
+fn push_raw_kv(text: &mut Vec<u8>, delimitter: u8, keyword: &[u8], value: &[u8]) -> Result<(), FcsError> {
+    // empty values are not allowed, a blank value is read back as empty after trimming
+    let value = if value.is_empty() { b" " } else { value };
+
+    for field in [keyword, value] {
+        // a leading delimitter would be read back as an escaped closing delimitter
+        if field.first() == Some(&delimitter) {
+            return Err(FcsError::InvalidKeywordValue {
+                keyword: String::from_utf8_lossy(keyword).to_string(),
+                value: String::from_utf8_lossy(value).to_string()
+            })
+        }
+
+        for &byte in field {
+            text.push(byte);
+            if byte == delimitter {
+                text.push(delimitter);
+            }
+        }
+        text.push(delimitter);
+    }
+
+    Ok(())
+}
+
+// Build the fixed width header segment
+fn build_header(version: &str, txt_end: u64, data_start: u64, data_end: u64) -> Vec<u8> {
+    // offsets that do not fit in the header are set to 0 and only stored in the text segment
+    let (data_start, data_end) = if data_end > MAX_HEADER_OFFSET {
+        (0, 0)
+    } else {
+        (data_start, data_end)
+    };
+
+    let header = format!(
+        "{:<6}    {:>8}{:>8}{:>8}{:>8}{:>8}{:>8}",
+        version, TEXT_START, txt_end, data_start, data_end, 0, 0
+    );
+    header.into_bytes()
+}
+
+// Encode the event data with the $DATATYPE and $BYTEORD of the metadata
+#[cfg(not(target_arch = "wasm32"))]
+fn encode_data(flowdata: &FlowData) -> Result<Vec<u8>, FcsError> {
+    let metadata = &flowdata.metadata;
+    let data_type = metadata.values.get("$DATATYPE")
+        .ok_or_else(|| FcsError::MissingKeyword("$DATATYPE".to_string()))?;
+    let byte_order = metadata.values.get("$BYTEORD")
+        .ok_or_else(|| FcsError::MissingKeyword("$BYTEORD".to_string()))?;
+    flowdata.to_interleaved_bytes(DataType::from_keyword_value(data_type)?, byte_order)
+}
+
+// Bits used to store each parameter with the given data type, integer widths come from $PnB
+fn bit_widths(flowdata: &FlowData, data_type: DataType) -> Result<Vec<usize>, FcsError> {
+    let metadata = &flowdata.metadata;
+
+    let mut bit_widths = Vec::with_capacity(flowdata.data.len());
+    for i in 0..flowdata.data.len() {
+        let keyword = format!("$P{}B", i+1);
+        let bits = match data_type {
+            DataType::Integer => {
+                let value = metadata.values.get(&keyword)
+                    .ok_or_else(|| FcsError::MissingKeyword(keyword.clone()))?;
+                match value.parse::<usize>() {
+                    Ok(bits) if [8, 16, 32, 64].contains(&bits) => bits,
+                    _ => return Err(FcsError::InvalidKeywordValue { keyword, value: value.to_string() })
+                }
+            },
+            // some writers store $DATATYPE F values as doubles with $PnB 64
+            DataType::Float if metadata.values.get(&keyword).map(|b| b.trim()) == Some("64") => 64,
+            DataType::Float => 32,
+            DataType::Double => 64,
+            DataType::Ascii => return Err(FcsError::UnsupportedDataType(data_type.keyword_value().to_string()))
+        };
+        bit_widths.push(bits);
+    }
+    Ok(bit_widths)
+}
+
+// Bytes used to store one event with the $DATATYPE of the metadata
+fn record_bytes(flowdata: &FlowData) -> Result<usize, FcsError> {
+    let data_type = flowdata.metadata.values.get("$DATATYPE")
+        .ok_or_else(|| FcsError::MissingKeyword("$DATATYPE".to_string()))?;
+    let data_type = DataType::from_keyword_value(data_type)?;
+    Ok(bit_widths(flowdata, data_type)?.iter().map(|bits| bits / 8).sum())
+}
+
+// Check whether a $BYTEORD value describes little endian (1,2,3,4) or big endian (4,3,2,1) order
+fn is_little_endian(byte_order: &str) -> Result<bool, FcsError> {
+    let order: Vec<&str> = byte_order.split(',').map(|b| b.trim()).collect();
+    let ascending: Vec<String> = (1..=order.len()).map(|b| b.to_string()).collect();
+    let descending: Vec<String> = ascending.iter().rev().cloned().collect();
+
+    if order.len() > 1 && order == ascending {
+        Ok(true)
+    } else if order.len() > 1 && order == descending {
+        Ok(false)
+    } else {
+        Err(FcsError::unsupported_byte_order(byte_order))
+    }
+}
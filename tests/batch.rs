@@ -0,0 +1,37 @@
+mod common;
+
+use flowfairy_api::{read_fcs_dir, FcsError, FcsReadOptions};
+use std::{env, fs};
+
+#[test]
+pub fn test_read_fcs_dir() -> Result<(), FcsError> {
+    let dir = env::temp_dir().join("flowfairy_read_fcs_dir");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("nested"))?;
+
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024")]);
+    let good = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    fs::write(dir.join("a_good.fcs"), &good)?;
+    fs::write(dir.join("b_malformed.FCS"), b"FCS3.1    not a header")?;
+    fs::write(dir.join("notes.txt"), b"not an fcs file")?;
+    fs::write(dir.join("nested").join("c_nested.fcs"), &good)?;
+
+    let results = read_fcs_dir(dir.to_str().unwrap(), FcsReadOptions::default());
+    assert_eq!(results.len(), 2);
+    assert!(results[0].0.ends_with("a_good.fcs"));
+    assert_eq!(results[0].1.as_ref().unwrap().data[0].events, vec![1.0, 2.0]);
+    assert!(results[1].0.ends_with("b_malformed.FCS"));
+    assert!(matches!(results[1].1, Err(FcsError::InvalidHeader(_))));
+
+    let opts = FcsReadOptions { recursive: true, threads: Some(2), ..FcsReadOptions::default() };
+    let results = read_fcs_dir(dir.to_str().unwrap(), opts);
+    assert_eq!(results.len(), 3);
+    assert!(results[2].0.ends_with("c_nested.fcs"));
+    assert!(results[2].1.is_ok());
+
+    let results = read_fcs_dir(dir.join("missing").to_str().unwrap(), FcsReadOptions::default());
+    assert_eq!(results.len(), 1);
+    assert!(matches!(results[0].1, Err(FcsError::Io(_))));
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// Helpers for building small FCS fixtures in memory. Not all test crates use every helper.
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+
+/// Ordered keyword/value pairs of a text segment
+pub type Keywords = Vec<(String, String)>;
+
+/// Placeholder values replaced with the computed data segment offsets by `build_fcs`
+pub const BEGINDATA: &str = "{BEGINDATA}";
+pub const ENDDATA: &str = "{ENDDATA}";
+
+/// Required list mode keywords for a data set with the given parameters (name, $PnB, $PnR).
+/// The data offsets are left as placeholders for `build_fcs` to fill in.
+pub fn list_mode_keywords(datatype: &str, byteord: &str, tot: usize, params: &[(&str, &str, &str)]) -> Vec<(String, String)> {
+    let mut keywords: Vec<(String, String)> = [
+        ("$BEGINANALYSIS", "0"),
+        ("$ENDANALYSIS", "0"),
+        ("$BEGINSTEXT", "0"),
+        ("$ENDSTEXT", "0"),
+        ("$BEGINDATA", BEGINDATA),
+        ("$ENDDATA", ENDDATA),
+        ("$MODE", "L"),
+        ("$DATATYPE", datatype),
+        ("$BYTEORD", byteord),
+        ("$NEXTDATA", "0"),
+    ].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    keywords.push(("$PAR".to_string(), params.len().to_string()));
+    keywords.push(("$TOT".to_string(), tot.to_string()));
+
+    for (i, (name, bits, range)) in params.iter().enumerate() {
+        keywords.push((format!("$P{}N", i+1), name.to_string()));
+        keywords.push((format!("$P{}B", i+1), bits.to_string()));
+        keywords.push((format!("$P{}E", i+1), "0,0".to_string()));
+        keywords.push((format!("$P{}R", i+1), range.to_string()));
+    }
+    keywords
+}
+
+/// Set a keyword value, appending the keyword if it is not present
+pub fn set_keyword(keywords: &mut Vec<(String, String)>, keyword: &str, value: &str) {
+    match keywords.iter_mut().find(|(k, _)| k == keyword) {
+        Some((_, v)) => *v = value.to_string(),
+        None => keywords.push((keyword.to_string(), value.to_string()))
+    }
+}
+
+/// Remove a keyword if present
+pub fn remove_keyword(keywords: &mut Vec<(String, String)>, keyword: &str) {
+    keywords.retain(|(k, _)| k != keyword);
+}
+
+/// Build the text segment bytes, keywords and values are written verbatim
+pub fn build_text(delimiter: u8, keywords: &[(String, String)]) -> Vec<u8> {
+    let mut text = vec![delimiter];
+    for (keyword, value) in keywords {
+        text.extend_from_slice(keyword.as_bytes());
+        text.push(delimiter);
+        text.extend_from_slice(value.as_bytes());
+        text.push(delimiter);
+    }
+    text
+}
+
+/// Build a complete single data set FCS file. Keyword values equal to `BEGINDATA`/`ENDDATA`
+/// are replaced with the offsets of the data segment, which directly follows the text segment.
+pub fn build_fcs(version: &str, delimiter: u8, keywords: &[(String, String)], data: &[u8]) -> Vec<u8> {
+    let mut data_start = 0usize;
+    loop {
+        let data_end = data_start + data.len().max(1) - 1;
+        let resolved: Vec<(String, String)> = keywords.iter()
+            .map(|(k, v)| {
+                let v = match v.as_str() {
+                    BEGINDATA => data_start.to_string(),
+                    ENDDATA => data_end.to_string(),
+                    _ => v.to_string()
+                };
+                (k.to_string(), v)
+            })
+            .collect();
+        let text = build_text(delimiter, &resolved);
+        let txt_end = 58 + text.len() - 1;
+
+        if txt_end + 1 == data_start {
+            let mut bytes = format!(
+                "{:<6}    {:>8}{:>8}{:>8}{:>8}{:>8}{:>8}",
+                version, 58, txt_end, data_start, data_end, 0, 0
+            ).into_bytes();
+            bytes.extend_from_slice(&text);
+            bytes.extend_from_slice(data);
+            return bytes
+        }
+        data_start = txt_end + 1;
+    }
+}
+
+/// Write fixture bytes to a uniquely named file in the temp directory and return its path
+pub fn write_fixture(name: &str, bytes: &[u8]) -> String {
+    let path = env::temp_dir().join(format!("flowfairy_{}.fcs", name));
+    fs::write(&path, bytes).expect("Unable to write fixture");
+    path.to_str().unwrap().to_string()
+}
+
+/// Encode values as little endian f32 in event-major order
+pub fn f32_le(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Encode values as little endian f64 in event-major order
+pub fn f64_le(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Build a file of several data sets chained through $NEXTDATA. Each data set is given as
+/// (version, keywords, data) and must contain a $NEXTDATA keyword, which is overwritten.
+pub fn build_multi_fcs(datasets: &[(&str, Keywords, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for (i, (version, keywords, data)) in datasets.iter().enumerate() {
+        let mut keywords = keywords.clone();
+        // fixed width so the data set length does not depend on the $NEXTDATA value
+        set_keyword(&mut keywords, "$NEXTDATA", "00000000");
+        let dataset = build_fcs(version, b'|', &keywords, data);
+
+        if i + 1 < datasets.len() {
+            set_keyword(&mut keywords, "$NEXTDATA", &format!("{:08}", dataset.len()));
+            bytes.extend(build_fcs(version, b'|', &keywords, data));
+        } else {
+            bytes.extend(dataset);
+        }
+    }
+    bytes
+}
+
+/// Encode an unsigned integer in `order.len()` bytes, where order holds the significance
+/// (1 = least significant) of each stored byte as in $BYTEORD
+pub fn int_bytes(value: u64, order: &[usize]) -> Vec<u8> {
+    order.iter().map(|significance| (value >> (8 * (significance - 1))) as u8).collect()
+}
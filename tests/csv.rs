@@ -0,0 +1,54 @@
+use flowfairy_api::{read_fcs, write_fcs, CsvImportOptions, FcsError, FlowData, Parameter};
+use std::env;
+use std::fs;
+
+#[test]
+pub fn test_csv_import_round_trip() -> Result<(), FcsError> {
+    let csv_path = env::temp_dir().join("flowfairy_csv_import.csv");
+    let fcs_path = env::temp_dir().join("flowfairy_csv_import.fcs");
+    fs::write(&csv_path, "FSC-A,SSC-A,FL1-A\n1.5,200,-3\n2.25,400,0.125\n\n10,800,42\n")?;
+
+    let flowdata = FlowData::from_csv(csv_path.to_str().unwrap(), CsvImportOptions::default())?;
+    assert_eq!(flowdata.data.len(), 3);
+    assert_eq!(flowdata.metadata.values.get("$PAR").unwrap(), "3");
+    assert_eq!(flowdata.metadata.values.get("$TOT").unwrap(), "3");
+    assert_eq!(flowdata.metadata.values.get("$P2N").unwrap(), "SSC-A");
+
+    write_fcs(&flowdata, fcs_path.to_str().unwrap())?;
+    let read_back = read_fcs(fcs_path.to_str().unwrap())?;
+
+    assert_eq!(read_back.metadata.version, "FCS3.1");
+    assert_eq!(read_back.metadata.keywords, flowdata.metadata.keywords);
+    for (written, read) in flowdata.data.iter().zip(read_back.data.iter()) {
+        assert_eq!(written.id, read.id);
+        assert_eq!(written.events, read.events);
+    }
+    assert_eq!(read_back.data[2].events, vec![-3.0, 0.125, 42.0]);
+
+    // rows with the wrong number of fields are rejected
+    fs::write(&csv_path, "A,B\n1,2\n3\n")?;
+    let result = FlowData::from_csv(csv_path.to_str().unwrap(), CsvImportOptions::default());
+    assert!(matches!(result, Err(FcsError::InvalidCsv { line: 3, .. })));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_parameter_to_csv() -> Result<(), FcsError> {
+    let path = env::temp_dir().join("flowfairy_parameter.csv");
+    let path = path.to_str().unwrap();
+    let parameter = Parameter { id: "FL1-A".to_string(), events: vec![1.5, -0.1, 1.0e-12, 250000.0] };
+
+    parameter.to_csv(path, true)?;
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("FL1-A"));
+    let values: Vec<f64> = lines.map(|line| line.parse().unwrap()).collect();
+    assert_eq!(values, parameter.events);
+
+    parameter.to_csv(path, false)?;
+    let values: Vec<f64> = fs::read_to_string(path)?.lines().map(|line| line.parse().unwrap()).collect();
+    assert_eq!(values, parameter.events);
+
+    Ok(())
+}
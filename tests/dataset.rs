@@ -0,0 +1,91 @@
+mod common;
+
+use flowfairy_api::{read_fcs_all, FcsDatasetIterator, FcsError};
+
+#[test]
+pub fn test_lazy_dataset_iteration() -> Result<(), FcsError> {
+    let first = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+    let mut second = common::list_mode_keywords("F", "1,2,3,4", 3, &[("FL1-A", "32", "1024")]);
+    common::set_keyword(&mut second, "$DATE", "02-Jan-2023");
+    let bytes = common::build_multi_fcs(&[
+        ("FCS3.0", first, common::f32_le(&[1.0, 2.0, 3.0, 4.0])),
+        ("FCS3.1", second, common::f32_le(&[5.0, 6.0, 7.0])),
+    ]);
+    let path = common::write_fixture("lazy_datasets", &bytes);
+
+    let datasets: Vec<_> = FcsDatasetIterator::open(&path)?.collect::<Result<_, _>>()?;
+    assert_eq!(datasets.len(), 2);
+    assert_eq!(datasets[0].offset(), 0);
+    assert_eq!(datasets[0].metadata.values.get("$PAR").unwrap(), "2");
+    assert_eq!(datasets[1].metadata.version, "FCS3.1");
+    assert_eq!(datasets[1].metadata.values.get("$DATE").unwrap(), "02-Jan-2023");
+
+    // only the second data set's data segment is read
+    let second = datasets.into_iter().nth(1).unwrap().load()?;
+    assert_eq!(second.data.len(), 1);
+    assert_eq!(second.data[0].id, "FL1-A");
+    assert_eq!(second.data[0].events, vec![5.0, 6.0, 7.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_read_fcs_all() -> Result<(), FcsError> {
+    let datasets: Vec<_> = (1..=3)
+        .map(|n| {
+            let keywords = common::list_mode_keywords("F", "1,2,3,4", n, &[("FSC-A", "32", "1024")]);
+            let events: Vec<f32> = (0..n).map(|i| (10 * n + i) as f32).collect();
+            ("FCS3.1", keywords, common::f32_le(&events))
+        })
+        .collect();
+    let mut bytes = common::build_multi_fcs(&datasets);
+    let path = common::write_fixture("read_fcs_all", &bytes);
+
+    let flowdata = read_fcs_all(&path)?;
+    assert_eq!(flowdata.len(), 3);
+    assert_eq!(flowdata[0].data[0].events, vec![10.0]);
+    assert_eq!(flowdata[1].data[0].events, vec![20.0, 21.0]);
+    assert_eq!(flowdata[2].data[0].events, vec![30.0, 31.0, 32.0]);
+
+    // the second data set links to the third with an offset from the start of the file
+    let pattern = b"$NEXTDATA|";
+    let links: Vec<usize> = (0..bytes.len() - pattern.len())
+        .filter(|&i| &bytes[i..i + pattern.len()] == pattern)
+        .map(|i| i + pattern.len())
+        .collect();
+    let link = |bytes: &[u8], at: usize| std::str::from_utf8(&bytes[at..at + 8]).unwrap().parse::<usize>().unwrap();
+    let (second, third) = (link(&bytes, links[0]), link(&bytes, links[1]));
+    bytes[links[1]..links[1] + 8].copy_from_slice(format!("{:08}", second + third).as_bytes());
+    let path = common::write_fixture("read_fcs_all_absolute", &bytes);
+
+    let flowdata = read_fcs_all(&path)?;
+    assert_eq!(flowdata.len(), 3);
+    assert_eq!(flowdata[2].data[0].events, vec![30.0, 31.0, 32.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_next_data_overflow() -> Result<(), FcsError> {
+    let first = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    let mut second = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    let mut bytes = common::build_multi_fcs(&[
+        ("FCS3.1", first, common::f32_le(&[1.0])),
+        ("FCS3.1", second.clone(), common::f32_le(&[2.0])),
+    ]);
+    // replace the second data set by one whose $NEXTDATA overflows its offset
+    bytes.truncate(bytes.len() - common::build_multi_fcs(&[("FCS3.1", second.clone(), common::f32_le(&[2.0]))]).len());
+    common::set_keyword(&mut second, "$NEXTDATA", &u64::MAX.to_string());
+    bytes.extend(common::build_fcs("FCS3.1", b'|', &second, &common::f32_le(&[2.0])));
+    let path = common::write_fixture("next_data_overflow", &bytes);
+
+    let mut datasets = FcsDatasetIterator::open(&path)?;
+    assert!(datasets.next().unwrap().is_ok());
+    match datasets.next() {
+        Some(Err(FcsError::InvalidKeywordValue { keyword, .. })) => assert_eq!(keyword, "$NEXTDATA"),
+        other => panic!("expected an invalid $NEXTDATA, got {:?}", other.map(|dataset| dataset.map(|d| d.offset())))
+    }
+    assert!(datasets.next().is_none());
+
+    Ok(())
+}
@@ -1,10 +1,14 @@
-use flowfairy_api::read_fcs;
-use std::io;
+mod common;
 
-const FORMAT_3_0_TESTFILE: &str = "/Users/nsbuitrago/Dev/flowfairy-api/tests/test_fcs_files/format_3_0.fcs";
+use flowfairy_api::{estimated_memory, AsciiSeparator, file_has_keyword_value, infer_datatype, DataType, read_fcs, read_fcs_from, read_fcs_from_bytes, read_fcs_head, read_fcs_with_options, read_fcs_with_progress, read_metadata_only, FcsError, FcsReadOptions};
+
+const FORMAT_3_0_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_3_0.fcs");
+const FORMAT_2_0_BIG_ENDIAN_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_2_0_big_endian.fcs");
+const FORMAT_3_0_ASCII_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_3_0_ascii.fcs");
+const FORMAT_3_0_SUPPLEMENTAL_TEXT_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_3_0_supplemental_text.fcs");
 
 #[test]
-pub fn test_fcs_3_0_reader() -> Result<(), io::Error>{
+pub fn test_fcs_3_0_reader() -> Result<(), FcsError>{
     // read FCS 3.0
     let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
     // check metadata
@@ -33,4 +37,1082 @@ pub fn test_fcs_3_0_reader() -> Result<(), io::Error>{
     Ok(())
 }
 
+#[test]
+pub fn test_float_datatype_with_64_bit_parameters() -> Result<(), FcsError> {
+    // $DATATYPE F but $PnB 64, the values are stored as doubles
+    let events = [1.0e-10, 250000.125, 2.5, -7.75, 3.0e10, 0.1];
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 3, &[("FSC-A", "64", "1024"), ("SSC-A", "64", "1024")]);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f64_le(&events));
+    let path = common::write_fixture("float_64_bit_parameters", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![1.0e-10, 2.5, 3.0e10]);
+    assert_eq!(flowdata.data[1].events, vec![250000.125, -7.75, 0.1]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_zero_width_parameter() -> Result<(), FcsError> {
+    // the second parameter is a placeholder without any bytes in the data segment
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024"), ("EMPTY", "0", "0"), ("SSC-A", "32", "1024")]);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.0, 2.0, 3.0, 4.0]));
+    let path = common::write_fixture("zero_width_parameter", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data.len(), 3);
+    assert_eq!(flowdata.data[0].events, vec![1.0, 3.0]);
+    assert_eq!(flowdata.data[1].id, "EMPTY");
+    assert_eq!(flowdata.data[1].events, vec![0.0, 0.0]);
+    assert_eq!(flowdata.data[2].events, vec![2.0, 4.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_file_has_keyword_value() -> Result<(), FcsError> {
+    assert!(file_has_keyword_value(FORMAT_3_0_TESTFILE, "$CYT", "LE-MA900FP")?);
+    assert!(!file_has_keyword_value(FORMAT_3_0_TESTFILE, "$CYT", "Aurora")?);
+    assert!(!file_has_keyword_value(FORMAT_3_0_TESTFILE, "$PLATENAME", "LE-MA900FP")?);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_escaped_delimitter() -> Result<(), FcsError> {
+    // a delimitter inside a value is stored doubled
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$COM", "a||b");
+    common::set_keyword(&mut keywords, "$SMNO", "tube||||1||");
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.5]));
+    let path = common::write_fixture("escaped_delimitter", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values.get("$COM").unwrap(), "a|b");
+    assert_eq!(flowdata.metadata.values.get("$SMNO").unwrap(), "tube||1|");
+    assert_eq!(flowdata.data[0].events, vec![1.5]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_read_fcs_head() -> Result<(), FcsError> {
+    let events: Vec<f32> = (0..1000).flat_map(|j| [j as f32, 1000.0 + j as f32]).collect();
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 1000, &[("TIME", "32", "1024"), ("FSC-A", "32", "2048")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&events));
+    let path = common::write_fixture("read_head", &bytes);
+
+    let flowdata = read_fcs_head(&path, 100)?;
+    assert_eq!(flowdata.metadata.values.get("$TOT").unwrap(), "100");
+    assert_eq!(flowdata.data[0].events.len(), 100);
+    assert_eq!(flowdata.data[1].events.len(), 100);
+    for j in 0..100 {
+        assert_eq!(flowdata.data[0].events[j], j as f64);
+        assert_eq!(flowdata.data[1].events[j], 1000.0 + j as f64);
+    }
+
+    // asking for more events than available reads them all
+    let flowdata = read_fcs_head(&path, 5000)?;
+    assert_eq!(flowdata.metadata.values.get("$TOT").unwrap(), "1000");
+    assert_eq!(flowdata.data[1].events[999], 1999.0);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_inflated_total_events() {
+    // $TOT claims 5 events but the data segment only holds 3
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 5, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+    let mut bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]));
+    // an analysis segment after the data must not be read as events
+    bytes.extend(common::f32_le(&[7.0, 8.0, 9.0, 10.0]));
+    let path = common::write_fixture("inflated_total_events", &bytes);
+
+    let result = read_fcs(&path);
+    assert!(matches!(result, Err(FcsError::TruncatedData { expected: 5, available: 3 })));
+}
+
+#[test]
+pub fn test_duplicate_keywords() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    keywords.push(("$COM".to_string(), "first comment".to_string()));
+    keywords.push(("$CYT".to_string(), "cytometer".to_string()));
+    keywords.push(("$COM".to_string(), "second comment".to_string()));
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("duplicate_keywords", &bytes);
+
+    let metadata = read_fcs(&path)?.metadata;
+    assert_eq!(metadata.keywords.iter().filter(|k| *k == "$COM").count(), 1);
+    assert_eq!(metadata.keywords.len(), metadata.values.len());
+    assert_eq!(&metadata.keywords[metadata.keywords.len()-2..], ["$COM", "$CYT"]);
+    assert_eq!(metadata.values.get("$COM").unwrap(), "second comment");
+    assert_eq!(metadata.warnings.len(), 1);
+    assert!(metadata.warnings[0].contains("$COM"));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_integer_mixed_widths_and_byte_orders() -> Result<(), FcsError> {
+    // "2,1,4,3" stores 16-bit values big endian and 32-bit values as swapped 16-bit words
+    let params = [("FL1-A", "16", "65536"), ("FL2-A", "32", "4294967296"), ("FL3-A", "8", "256")];
+    let keywords = common::list_mode_keywords("I", "2,1,4,3", 2, &params);
+    let mut data = Vec::new();
+    for (a, b, c) in [(0x1234, 0x0A0B0C0D, 0x7F), (0xFFFE, 0x00010002, 0x01)] {
+        data.extend(common::int_bytes(a, &[2, 1]));
+        data.extend(common::int_bytes(b, &[2, 1, 4, 3]));
+        data.extend(common::int_bytes(c, &[1]));
+    }
+    assert_eq!(&data[..6], &[0x12, 0x34, 0x0C, 0x0D, 0x0A, 0x0B]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+    let path = common::write_fixture("integer_mixed_widths", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![4660.0, 65534.0]);
+    assert_eq!(flowdata.data[1].events, vec![168496141.0, 65538.0]);
+    assert_eq!(flowdata.data[2].events, vec![127.0, 1.0]);
+
+    // plain little and big endian orders within the same widths
+    for (byte_order, order16, order32) in [("1,2,3,4", [1, 2], [1, 2, 3, 4]), ("4,3,2,1", [2, 1], [4, 3, 2, 1])] {
+        let params = [("FL1-A", "16", "65536"), ("FL2-A", "32", "4294967296")];
+        let keywords = common::list_mode_keywords("I", byte_order, 1, &params);
+        let mut data = common::int_bytes(0x1234, &order16);
+        data.extend(common::int_bytes(0x0A0B0C0D, &order32));
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+        let path = common::write_fixture("integer_endian_widths", &bytes);
+
+        let flowdata = read_fcs(&path)?;
+        assert_eq!(flowdata.data[0].events, vec![4660.0]);
+        assert_eq!(flowdata.data[1].events, vec![168496141.0]);
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_unsupported_byte_order() {
+    for (byte_order, parsed, suggestion) in [
+        ("1;2;3;4", vec![1, 2, 3, 4], "1,2,3,4"),
+        ("4,3,3,1", vec![4, 3, 3, 1], "4,3,2,1"),
+    ] {
+        let keywords = common::list_mode_keywords("I", byte_order, 1, &[("FL1-A", "16", "1024")]);
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &[1, 0]);
+        let path = common::write_fixture("unsupported_byte_order", &bytes);
+
+        match read_fcs(&path) {
+            Err(FcsError::UnsupportedByteOrder { byte_order: raw, parsed: p, suggestion: s }) => {
+                assert_eq!(raw, byte_order);
+                assert_eq!(p, parsed);
+                assert_eq!(s.as_deref(), Some(suggestion));
+            },
+            _ => panic!("expected an unsupported byte order error")
+        }
+    }
+}
+
+#[test]
+pub fn test_nul_padded_data_segment() -> Result<(), FcsError> {
+    let events = common::f32_le(&[1.0, 2.0, 3.0, 4.0]);
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+
+    // padding after $ENDDATA
+    let mut bytes = common::build_fcs("FCS3.0", b'|', &keywords, &events);
+    bytes.resize(bytes.len() + 512, 0);
+    let path = common::write_fixture("nul_padding_after_end", &bytes);
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[1].events, vec![2.0, 4.0]);
+
+    // padding included in the declared data segment
+    let mut padded = events.clone();
+    padded.resize(events.len() + 13, 0);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &padded);
+    let path = common::write_fixture("nul_padding_before_end", &bytes);
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![1.0, 3.0]);
+    assert_eq!(flowdata.data[1].events, vec![2.0, 4.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_native_fast_path_matches_generic_reader() -> Result<(), FcsError> {
+    // one of each byte order pair is read natively in bulk, the other float is byte swapped in
+    // bulk and the other integer is read value by value
+    let values: Vec<u64> = (0..300).map(|i| (i * 7919) % 65536).collect();
+    let cases: [(&str, &str, &str, usize); 6] = [
+        ("F", "1,2,3,4", "32", 4), ("F", "4,3,2,1", "32", 4),
+        ("D", "1,2,3,4,5,6,7,8", "64", 8), ("D", "8,7,6,5,4,3,2,1", "64", 8),
+        ("I", "1,2,3,4", "16", 2), ("I", "4,3,2,1", "16", 2),
+    ];
+
+    let mut decoded = Vec::new();
+    for (datatype, byte_order, bits, width) in cases {
+        let little_endian = byte_order.starts_with('1');
+        let data: Vec<u8> = values.iter()
+            .flat_map(|&v| {
+                let mut bytes = match datatype {
+                    "F" => (v as f32).to_le_bytes().to_vec(),
+                    "D" => (v as f64).to_le_bytes().to_vec(),
+                    _ => (v as u16).to_le_bytes().to_vec()
+                };
+                assert_eq!(bytes.len(), width);
+                if !little_endian {
+                    bytes.reverse();
+                }
+                bytes
+            })
+            .collect();
+        let params = [("FSC-A", bits, "65536"), ("SSC-A", bits, "65536"), ("FL1-A", bits, "65536")];
+        let keywords = common::list_mode_keywords(datatype, byte_order, values.len() / 3, &params);
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+        let path = common::write_fixture(&format!("fast_path_{}_{}", datatype, little_endian), &bytes);
+
+        let flowdata = read_fcs(&path)?;
+        let events: Vec<Vec<f64>> = flowdata.data.into_iter().map(|p| p.events).collect();
+        assert_eq!(events[1][0], values[1] as f64);
+        decoded.push(events);
+    }
+
+    for pair in decoded.chunks(2) {
+        assert_eq!(pair[0], pair[1]);
+    }
+    assert_eq!(decoded[0], decoded[4]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_unexpanded_template_keyword() {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$PnN", "SSC-A");
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("unexpanded_template", &bytes);
+
+    match read_fcs(&path) {
+        Err(FcsError::UnexpandedTemplate(keyword)) => assert_eq!(keyword, "$PnN"),
+        _ => panic!("expected an unexpanded template error")
+    }
+}
+
+#[test]
+pub fn test_last_delimitter_at_txt_end() -> Result<(), FcsError> {
+    // the data segment starts right after the closing delimitter with a byte equal to it
+    let mut keywords = common::list_mode_keywords("I", "1", 3, &[("FSC-A", "8", "256")]);
+    keywords.push(("$CYT".to_string(), "LE-MA900FP".to_string()));
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &[b'|', 7, b'|']);
+    let txt_end: usize = std::str::from_utf8(&bytes[18..26]).unwrap().trim().parse().unwrap();
+    assert_eq!(bytes[txt_end], b'|');
+    assert_eq!(bytes[txt_end + 1], b'|');
+    let path = common::write_fixture("last_delimitter_at_txt_end", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values.get("$CYT").unwrap(), "LE-MA900FP");
+    assert_eq!(flowdata.metadata.keywords.last().unwrap(), "$CYT");
+    assert_eq!(flowdata.data[0].events, vec![124.0, 7.0, 124.0]);
+    assert!(file_has_keyword_value(&path, "$CYT", "LE-MA900FP")?);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_supplemental_text_overrides_datatype() -> Result<(), FcsError> {
+    // the primary text segment declares floats, the supplemental text segment after the data
+    // segment declares doubles
+    let events = [1.5, -2.25, 1.0e12, 7.0];
+    let data = common::f64_le(&events);
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$BEGINSTEXT", "00000000");
+    common::set_keyword(&mut keywords, "$ENDSTEXT", "00000000");
 
+    let stext = common::build_text(b'/', &[
+        ("$DATATYPE".to_string(), "D".to_string()),
+        ("$BYTEORD".to_string(), "1,2,3,4,5,6,7,8".to_string()),
+    ]);
+    let stext_start = common::build_fcs("FCS3.1", b'|', &keywords, &data).len();
+    common::set_keyword(&mut keywords, "$BEGINSTEXT", &format!("{:08}", stext_start));
+    common::set_keyword(&mut keywords, "$ENDSTEXT", &format!("{:08}", stext_start + stext.len() - 1));
+    let mut bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+    bytes.extend_from_slice(&stext);
+    let path = common::write_fixture("supplemental_text_datatype", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values.get("$DATATYPE").unwrap(), "D");
+    assert_eq!(flowdata.metadata.keywords.iter().filter(|k| *k == "$DATATYPE").count(), 1);
+    assert!(flowdata.metadata.warnings.is_empty());
+    assert_eq!(flowdata.data[0].events, vec![1.5, 1.0e12]);
+    assert_eq!(flowdata.data[1].events, vec![-2.25, 7.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_supplemental_text_file() -> Result<(), FcsError> {
+    // the primary text segment declares 32-bit integers, the supplemental text segment after the
+    // data segment declares floats and adds keywords of its own
+    let flowdata = read_fcs(FORMAT_3_0_SUPPLEMENTAL_TEXT_TESTFILE)?;
+    assert_eq!(flowdata.metadata.values.get("$DATATYPE").unwrap(), "F");
+    assert_eq!(flowdata.metadata.values.get("$P3S").unwrap(), "CD4 FITC");
+    assert!(flowdata.metadata.values.contains_key("$SPILLOVER"));
+    assert_eq!(flowdata.data[0].events, vec![1.5, 1.0e6, 42.0]);
+    assert_eq!(flowdata.data[1].events, vec![-2.25, 0.125, 7.75]);
+    assert_eq!(flowdata.data[2].events, vec![100.0, -3.0, 0.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_swapped_floats_bit_exact() -> Result<(), FcsError> {
+    // an odd number of values so the bulk swap has a remainder, including special values
+    let specials = [0.0, -0.0, f64::MIN_POSITIVE / 4.0, f64::INFINITY, f64::NEG_INFINITY, f64::MAX, 1.0e-300];
+    let values: Vec<f64> = specials.iter().copied()
+        .chain((0..38).map(|i| (i as f64 - 19.0) * 1234.5678))
+        .collect();
+    assert_eq!(values.len() % 3, 0);
+
+    for (datatype, width, orders) in [("F", 4, ["1,2,3,4", "4,3,2,1"]), ("D", 8, ["1,2,3,4,5,6,7,8", "8,7,6,5,4,3,2,1"])] {
+        let expected: Vec<u64> = values.iter()
+            .map(|&v| if width == 4 { (v as f32 as f64).to_bits() } else { v.to_bits() })
+            .collect();
+
+        for byte_order in orders {
+            let data: Vec<u8> = values.iter()
+                .flat_map(|&v| {
+                    let mut bytes = if width == 4 { (v as f32).to_le_bytes().to_vec() } else { v.to_le_bytes().to_vec() };
+                    if byte_order.starts_with(&width.to_string()) {
+                        bytes.reverse();
+                    }
+                    bytes
+                })
+                .collect();
+            let bits = (width * 8).to_string();
+            let params = [("FSC-A", bits.as_str(), "1024"), ("SSC-A", bits.as_str(), "1024"), ("FL1-A", bits.as_str(), "1024")];
+            let keywords = common::list_mode_keywords(datatype, byte_order, values.len() / 3, &params);
+            let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+            let path = common::write_fixture(&format!("swapped_{}_{}", datatype, &byte_order[..1]), &bytes);
+
+            let flowdata = read_fcs(&path)?;
+            let decoded: Vec<u64> = (0..values.len())
+                .map(|i| flowdata.data[i % 3].events[i / 3].to_bits())
+                .collect();
+            assert_eq!(decoded, expected, "{} {}", datatype, byte_order);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_ascii_scientific_notation() -> Result<(), FcsError> {
+    // delimited values separated by any whitespace
+    let data = b"1.23E+04 -5.5e-3\n+7 0001.5E2\r\n-0.25 3e0 ";
+    let keywords = common::list_mode_keywords("A", "1,2,3,4", 3, &[("FSC-A", "*", "1024"), ("SSC-A", "*", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, data);
+    let path = common::write_fixture("ascii_scientific_delimited", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![12300.0, 7.0, -0.25]);
+    assert_eq!(flowdata.data[1].events, vec![-0.0055, 150.0, 3.0]);
+
+    // fixed width values padded with spaces and zeros
+    let data = b"1.5E+02 -2.0E-01000012  -1E1    ";
+    let keywords = common::list_mode_keywords("A", "1,2,3,4", 2, &[("FSC-A", "8", "1024"), ("SSC-A", "8", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, data);
+    let path = common::write_fixture("ascii_scientific_fixed", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![150.0, 12.0]);
+    assert_eq!(flowdata.data[1].events, vec![-0.2, -10.0]);
+
+    // a value that is not a number
+    let keywords = common::list_mode_keywords("A", "1,2,3,4", 1, &[("FSC-A", "*", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, b"1.2.3");
+    let path = common::write_fixture("ascii_invalid", &bytes);
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidAsciiValue(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_data_too_large() {
+    // $PAR * $TOT overflows usize
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$TOT", &(usize::MAX / 2 + 1).to_string());
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    let path = common::write_fixture("data_too_large", &bytes);
+
+    assert!(matches!(read_fcs(&path), Err(FcsError::DataTooLarge { parameters: 2, .. })));
+
+    // a huge $TOT that does not overflow is reported as truncated data, without allocating
+    common::set_keyword(&mut keywords, "$TOT", &(usize::MAX / 4).to_string());
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    let path = common::write_fixture("data_too_large_truncated", &bytes);
+    assert!(matches!(read_fcs(&path), Err(FcsError::TruncatedData { available: 1, .. })));
+}
+
+#[test]
+pub fn test_read_fcs_from_bytes() -> Result<(), FcsError> {
+    let bytes = std::fs::read(FORMAT_3_0_TESTFILE)?;
+    let from_bytes = read_fcs_from_bytes(&bytes)?;
+    let from_file = read_fcs(FORMAT_3_0_TESTFILE)?;
+
+    assert_eq!(from_bytes.metadata.keywords, from_file.metadata.keywords);
+    assert_eq!(from_bytes.metadata.values, from_file.metadata.values);
+    for (a, b) in from_bytes.data.iter().zip(from_file.data.iter()) {
+        assert_eq!(a.id, b.id);
+        assert_eq!(a.events, b.events);
+    }
+
+    // a truncated buffer is an error, not a panic
+    assert!(matches!(read_fcs_from_bytes(&bytes[..bytes.len() / 2]), Err(FcsError::TruncatedData { .. }) | Err(FcsError::Io(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_header_offsets() -> Result<(), FcsError> {
+    let bytes = std::fs::read(FORMAT_3_0_TESTFILE)?;
+    let offset = |i: usize| -> u64 {
+        let field = &bytes[10 + 8 * i..18 + 8 * i];
+        std::str::from_utf8(field).unwrap().trim().parse().unwrap()
+    };
+
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let header = flowdata.header();
+    assert_eq!(header.version, "FCS3.0");
+    assert_eq!(header.txt_start, offset(0));
+    assert_eq!(header.txt_end, offset(1));
+    assert_eq!(header.data_start, offset(2));
+    assert_eq!(header.data_end, offset(3));
+    assert_eq!(header.data_start.to_string(), flowdata.metadata.values["$BEGINDATA"]);
+
+    // data offsets missing from the header are taken from the text segment
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024")]);
+    let mut bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    bytes[26..42].copy_from_slice(b"       0       0");
+    let path = common::write_fixture("header_offsets", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    let header = flowdata.header();
+    assert_eq!(header.txt_start, 58);
+    assert_eq!(header.data_start.to_string(), flowdata.metadata.values["$BEGINDATA"]);
+    assert_eq!(header.data_end, header.data_start + 7);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_escaped_delimitter_in_parameter_name() -> Result<(), FcsError> {
+    // "CD3|FITC" written with the delimitter escaped by doubling it
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("CD3||FITC", "32", "1024"), ("SSC-A", "32", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0, 3.0, 4.0]));
+    let path = common::write_fixture("escaped_parameter_name", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values["$P1N"], "CD3|FITC");
+    assert_eq!(flowdata.data[0].id, "CD3|FITC");
+    assert_eq!(flowdata.metadata.values["$P1B"], "32");
+    assert_eq!(flowdata.parameter_where("CD3|FITC", "SSC-A", 0.0, 3.0)?, vec![1.0]);
+    assert_eq!(flowdata.data[1].events, vec![2.0, 4.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_escaped_delimitter_in_comment() -> Result<(), FcsError> {
+    // values holding a delimitter in the middle, doubled delimitters, and one at the end
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$COM", "sort gate A//B, ratio 1////2 //");
+    common::set_keyword(&mut keywords, "$SMNO", "tube//");
+    common::set_keyword(&mut keywords, "$CYT", "MA900");
+    let bytes = common::build_fcs("FCS3.1", b'/', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("escaped_delimitter_comment", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values["$COM"], "sort gate A/B, ratio 1//2 /");
+    assert_eq!(flowdata.metadata.values["$SMNO"], "tube/");
+    // the keyword after an escaped delimitter at the end of a value is still found
+    let smno = flowdata.metadata.keywords.iter().position(|k| k == "$SMNO").unwrap();
+    assert_eq!(flowdata.metadata.keywords[smno + 1], "$CYT");
+    assert_eq!(flowdata.metadata.values["$CYT"], "MA900");
+    assert_eq!(flowdata.data[0].events, vec![1.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_crlf_contaminated_text() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$P1N", "\r\nFSC-A\r\n");
+    keywords.push(("\r\n$CYT\r".to_string(), "\r\nLE-MA900FP\r\n".to_string()));
+    keywords.push(("$C\rOM".to_string(), "first line\r\nsecond line\x1a".to_string()));
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("crlf_text", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].id, "FSC-A");
+    assert_eq!(flowdata.metadata.values["$CYT"], "LE-MA900FP");
+    assert_eq!(flowdata.metadata.values["$COM"], "first line\nsecond line");
+    assert!(flowdata.metadata.keywords.iter().all(|k| !k.contains('\r')));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_header_errors() {
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+
+    let mut unsupported = bytes.clone();
+    unsupported[..6].copy_from_slice(b"FCS9.9");
+    let path = common::write_fixture("unsupported_version", &unsupported);
+    assert!(matches!(read_fcs(&path), Err(FcsError::UnsupportedVersion(version)) if version == "FCS9.9"));
+
+    let mut spaces = bytes.clone();
+    spaces[7] = b'x';
+    let path = common::write_fixture("invalid_header_spaces", &spaces);
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidHeader(_))));
+
+    let mut offset = bytes.clone();
+    offset[12] = b'x';
+    let path = common::write_fixture("invalid_header_offset", &offset);
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidHeader(_))));
+
+    // files shorter than the version or the whole header
+    for (name, len) in [("short_version", 4), ("short_header", 30)] {
+        let path = common::write_fixture(name, &bytes[..len]);
+        assert!(matches!(read_fcs(&path), Err(FcsError::InvalidHeader(_))));
+    }
+    let path = common::write_fixture("empty_file", &[]);
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidHeader(_))));
+    assert!(matches!(read_fcs("/nonexistent/flowfairy.fcs"), Err(FcsError::Io(_))));
+}
+
+#[test]
+pub fn test_fcs_2_0() -> Result<(), FcsError> {
+    // no segment offset keywords, the data offsets are only in the header
+    let mut keywords = common::list_mode_keywords("I", "4,3,2,1", 2, &[("FSC-H", "16", "1024"), ("SSC-H", "16", "1024")]);
+    for keyword in ["$BEGINANALYSIS", "$ENDANALYSIS", "$BEGINSTEXT", "$ENDSTEXT", "$BEGINDATA", "$ENDDATA"] {
+        common::remove_keyword(&mut keywords, keyword);
+    }
+    let data: Vec<u8> = [1u64, 1023, 512, 7].iter().flat_map(|&v| common::int_bytes(v, &[2, 1])).collect();
+    let bytes = common::build_fcs("FCS2.0", b'/', &keywords, &data);
+    let path = common::write_fixture("fcs_2_0", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.version, "FCS2.0");
+    assert!(!flowdata.metadata.values.contains_key("$BEGINDATA"));
+    assert_eq!(flowdata.data[0].events, vec![1.0, 512.0]);
+    assert_eq!(flowdata.data[1].events, vec![1023.0, 7.0]);
+
+    // 3.x files still need the offset keywords
+    let mut bytes = bytes;
+    bytes[..6].copy_from_slice(b"FCS3.0");
+    let path = common::write_fixture("fcs_3_0_without_offsets", &bytes);
+    assert!(matches!(read_fcs(&path), Err(FcsError::MissingKeyword(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_missing_mode() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024")]);
+    common::remove_keyword(&mut keywords, "$MODE");
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    let path = common::write_fixture("missing_mode", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![1.0, 2.0]);
+    assert!(!flowdata.metadata.values.contains_key("$MODE"));
+    assert!(flowdata.metadata.warnings.iter().any(|w| w.contains("$MODE")));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_fcs_3_2_keywords() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$CARRIERID", "plate-7");
+    common::set_keyword(&mut keywords, "$LOCATIONID", "B4");
+    common::set_keyword(&mut keywords, "$UNSTAINEDCENTERS", "1,FSC-A,12.5");
+    let bytes = common::build_fcs("FCS3.2", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    let path = common::write_fixture("fcs_3_2", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.version, "FCS3.2");
+    assert_eq!(flowdata.metadata.values.get("$LOCATIONID").unwrap(), "B4");
+    assert_eq!(flowdata.data[0].events, vec![1.0, 2.0]);
+
+    // 3.2 keywords are not part of earlier versions
+    let mut bytes = bytes;
+    bytes[..6].copy_from_slice(b"FCS3.1");
+    let path = common::write_fixture("fcs_3_1_with_3_2_keywords", &bytes);
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidKeyword(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_read_fcs_with_progress() -> Result<(), FcsError> {
+    let mut calls: Vec<f64> = Vec::new();
+    let flowdata = read_fcs_with_progress(FORMAT_3_0_TESTFILE, |fraction| calls.push(fraction))?;
+    assert_eq!(flowdata.data[0].events, read_fcs(FORMAT_3_0_TESTFILE)?.data[0].events);
+
+    assert!(!calls.is_empty());
+    assert!(calls.windows(2).all(|w| w[0] <= w[1]), "{:?}", calls);
+    assert!(calls.iter().all(|fraction| (0.0..=1.0).contains(fraction)));
+    assert_eq!(*calls.last().unwrap(), 1.0);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_ascii_integers() -> Result<(), FcsError> {
+    // fixed width per parameter, zero padded on the left, with padding after the last event
+    let data = b"00120003450007123456   \0\0";
+    let keywords = common::list_mode_keywords("A", "1,2,3,4", 2, &[("FSC-A", "4", "1024"), ("SSC-A", "6", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, data);
+    let path = common::write_fixture("ascii_integers_fixed", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![12.0, 7.0]);
+    assert_eq!(flowdata.data[1].events, vec![345.0, 123456.0]);
+
+    // delimited values with NUL padding after the last event
+    let data = b"0042 7\t00100 0\0\0\0";
+    let keywords = common::list_mode_keywords("A", "1,2,3,4", 2, &[("FSC-A", "*", "1024"), ("SSC-A", "*", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, data);
+    let path = common::write_fixture("ascii_integers_delimited", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![42.0, 100.0]);
+    assert_eq!(flowdata.data[1].events, vec![7.0, 0.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_mixed_integer_widths() -> Result<(), FcsError> {
+    // every parameter is read with the width of its own $PnB
+    let params = [("FSC-H", "16", "65536"), ("SSC-H", "32", "4294967296"), ("FL1-H", "8", "256"), ("Time", "64", "0")];
+    let events: [[u64; 4]; 2] = [[65535, 70000, 200, 1 << 40], [3, 4_000_000_000, 0, 12]];
+    for (byte_order, order) in [("1,2,3,4", [1usize, 2, 3, 4, 5, 6, 7, 8]), ("4,3,2,1", [8, 7, 6, 5, 4, 3, 2, 1])] {
+        let keywords = common::list_mode_keywords("I", byte_order, 2, &params);
+        let data: Vec<u8> = events.iter()
+            .flat_map(|event| event.iter().zip(params.iter()).map(|(&value, (_, bits, _))| (value, bits.parse::<usize>().unwrap() / 8)))
+            .flat_map(|(value, width)| {
+                let order: Vec<usize> = order.iter().copied().filter(|&b| b <= width).collect();
+                common::int_bytes(value, &order)
+            })
+            .collect();
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+        let path = common::write_fixture(&format!("mixed_integer_widths_{}", byte_order), &bytes);
+
+        let flowdata = read_fcs(&path)?;
+        for (i, parameter) in flowdata.data.iter().enumerate() {
+            assert_eq!(parameter.events, vec![events[0][i] as f64, events[1][i] as f64], "{}", parameter.id);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_range_mask_power_of_two() -> Result<(), FcsError> {
+    // a range of 1000 masks to the 10 bits of the next power of two, 1024
+    let values = [999u64, 1023, 1029, 0xFFFF];
+    let keywords = common::list_mode_keywords("I", "1,2", 4, &[("FSC-H", "16", "1000")]);
+    let data: Vec<u8> = values.iter().flat_map(|&v| common::int_bytes(v, &[1, 2])).collect();
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+    let path = common::write_fixture("range_mask_1000", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![999.0, 1023.0, 5.0, 1023.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_range_mask_high_bits() -> Result<(), FcsError> {
+    // 16-bit values with a range of 1024 only keep 10 bits, a parameter without $PnR keeps all
+    let mut keywords = common::list_mode_keywords("I", "2,1", 2, &[("FSC-H", "16", "1024"), ("SSC-H", "16", "700"), ("FL1-H", "16", "1024")]);
+    common::remove_keyword(&mut keywords, "$P3R");
+    let values = [0xFC00u64 | 17, 0x0400 | 600, 0xFFFF, 1023, 0x8000 | 3, 0xFFFE];
+    let data: Vec<u8> = values.iter().flat_map(|&v| common::int_bytes(v, &[2, 1])).collect();
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+    let path = common::write_fixture("range_mask_high_bits", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].events, vec![17.0, 1023.0]);
+    assert_eq!(flowdata.data[1].events, vec![600.0, 3.0]);
+    assert_eq!(flowdata.data[2].events, vec![65535.0, 65534.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_read_metadata_only() -> Result<(), FcsError> {
+    let metadata = read_metadata_only(FORMAT_3_0_TESTFILE)?;
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    assert_eq!(metadata.keywords, flowdata.metadata.keywords);
+    assert_eq!(metadata.values, flowdata.metadata.values);
+    assert_eq!(metadata.header, flowdata.metadata.header);
+
+    // a data segment that is cut short does not matter when only the metadata is read
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 1000, &[("FSC-A", "32", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("metadata_only_truncated", &bytes);
+    assert!(matches!(read_fcs(&path), Err(FcsError::TruncatedData { .. })));
+    assert_eq!(read_metadata_only(&path)?.values.get("$TOT").unwrap(), "1000");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_ascii_separators() -> Result<(), FcsError> {
+    let keywords = common::list_mode_keywords("A", "1,2,3,4", 3, &[("FSC-A", "*", "1024"), ("SSC-A", "*", "1024")]);
+    for (name, data) in [
+        ("comma", &b"12,0034,\n5.5,-6,\n7E1,8"[..]),
+        ("delimiter", &b"12|0034|5.5|-6\r\n7E1|8|"[..]),
+        ("whitespace", &b"12 0034\t5.5 -6\n7E1 8"[..])
+    ] {
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, data);
+        let path = common::write_fixture(&format!("ascii_separator_{}", name), &bytes);
+
+        let flowdata = read_fcs(&path)?;
+        assert_eq!(flowdata.data[0].events, vec![12.0, 5.5, 70.0], "{}", name);
+        assert_eq!(flowdata.data[1].events, vec![34.0, -6.0, 8.0], "{}", name);
+    }
+
+    // semicolons are not detected, but can be given explicitly
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, b"12;0034;5.5;-6;7E1;8");
+    let path = common::write_fixture("ascii_separator_semicolon", &bytes);
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidAsciiValue(_))));
+    let options = FcsReadOptions { ascii_separator: Some(AsciiSeparator::Delimiter(b';')), ..FcsReadOptions::default() };
+    let flowdata = read_fcs_with_options(&path, &options)?;
+    assert_eq!(flowdata.data[0].events, vec![12.0, 5.5, 70.0]);
+    assert_eq!(flowdata.data[1].events, vec![34.0, -6.0, 8.0]);
+
+    // an explicit separator overrides detection, so the commas are left in the values
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, b"1,5 2,5 3 4 5 6");
+    let path = common::write_fixture("ascii_separator_forced_whitespace", &bytes);
+    let options = FcsReadOptions { ascii_separator: Some(AsciiSeparator::Whitespace), ..FcsReadOptions::default() };
+    assert!(matches!(read_fcs_with_options(&path, &options), Err(FcsError::InvalidAsciiValue(_))));
+    assert_eq!(read_fcs(&path)?.data[0].events, vec![1.0, 2.0, 3.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_read_fcs_from_reader() -> Result<(), FcsError> {
+    let bytes = std::fs::read(FORMAT_3_0_TESTFILE)?;
+    let flowdata = read_fcs_from(std::io::Cursor::new(bytes))?;
+    let expected = read_fcs(FORMAT_3_0_TESTFILE)?;
+    assert_eq!(flowdata.metadata.values, expected.metadata.values);
+    for (parameter, expected) in flowdata.data.iter().zip(expected.data.iter()) {
+        assert_eq!(parameter.events, expected.events);
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_infer_datatype() -> Result<(), FcsError> {
+    // floats labelled as integers
+    let events = [1.5f32, 250.25, -3.0, 1.0e4, 0.0, 42.0];
+    let keywords = common::list_mode_keywords("I", "1,2,3,4", 3, &[("FSC-A", "32", "262144"), ("SSC-A", "32", "262144")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&events));
+    let path = common::write_fixture("infer_datatype_float", &bytes);
+
+    assert_ne!(read_fcs(&path)?.data[0].events, vec![1.5, -3.0, 0.0]);
+    let metadata = read_metadata_only(&path)?;
+    let data_type = infer_datatype(&mut std::fs::File::open(&path)?, &metadata)?;
+    assert_eq!(data_type, DataType::Float);
+
+    // the inferred type in place of the stored one reads the events correctly
+    let mut fixed = bytes.clone();
+    let at = fixed.windows(13).position(|w| w == b"|$DATATYPE|I|").unwrap() + 11;
+    fixed[at] = data_type.keyword_value().as_bytes()[0];
+    let flowdata = read_fcs_from_bytes(&fixed)?;
+    assert_eq!(flowdata.data[0].events, vec![1.5, -3.0, 0.0]);
+    assert_eq!(flowdata.data[1].events, vec![250.25, 1.0e4, 42.0]);
+
+    // integers labelled as floats
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 3, &[("FSC-A", "32", "262144"), ("SSC-A", "32", "262144")]);
+    let data: Vec<u8> = [12u64, 262143, 0, 5000, 77, 1].iter().flat_map(|&v| common::int_bytes(v, &[1, 2, 3, 4])).collect();
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+    let metadata = read_fcs_from_bytes(&bytes)?.metadata;
+    assert_eq!(infer_datatype(&mut std::io::Cursor::new(&bytes), &metadata)?, DataType::Integer);
+
+    // doubles labelled as floats take twice the declared bytes
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 3, &[("FSC-A", "64", "262144")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f64_le(&[1.0, 2.0, 3.0]));
+    let metadata = read_fcs_from_bytes(&bytes)?.metadata;
+    assert_eq!(infer_datatype(&mut std::io::Cursor::new(&bytes), &metadata)?, DataType::Double);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_parameter_by_name() -> Result<(), FcsError> {
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let names = flowdata.parameter_names();
+    assert_eq!(names.len(), flowdata.data.len());
+    assert_eq!(names[0], flowdata.data[0].id);
+
+    let parameter = flowdata.parameter(names[1]).unwrap();
+    assert_eq!(parameter.events, flowdata.data[1].events);
+    let lowercase = flowdata.parameter(&names[1].to_lowercase()).unwrap();
+    assert_eq!(lowercase.id, names[1]);
+    assert!(flowdata.parameter("no such channel").is_none());
+
+    Ok(())
+}
+
+#[test]
+pub fn test_total_events_and_params() -> Result<(), FcsError> {
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    assert_eq!(flowdata.total_events()?, flowdata.data[0].events.len());
+    assert_eq!(flowdata.total_params()?, flowdata.data.len());
+
+    flowdata.metadata.insert("$TOT", "many");
+    assert!(matches!(flowdata.total_events(), Err(FcsError::InvalidKeywordValue { .. })));
+    flowdata.metadata.values.remove("$PAR");
+    assert!(matches!(flowdata.total_params(), Err(FcsError::MissingKeyword(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_double_with_4_byte_order() {
+    let keywords = common::list_mode_keywords("D", "1,2,3,4", 2, &[("FSC-A", "64", "1024")]);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f64_le(&[1.5, 2.5]));
+    let path = common::write_fixture("double_4_byte_order", &bytes);
+
+    match read_fcs(&path) {
+        Err(FcsError::ByteOrderWidthMismatch { byte_order, width }) => {
+            assert_eq!(byte_order, "1,2,3,4");
+            assert_eq!(width, 8);
+        },
+        other => panic!("expected ByteOrderWidthMismatch, got {:?}", other.map(|_| ()))
+    }
+
+    // FCS 3.1 gives only the endianness in $BYTEORD
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f64_le(&[1.5, 2.5]));
+    let flowdata = read_fcs_from_bytes(&bytes).unwrap();
+    assert_eq!(flowdata.data[0].events, vec![1.5, 2.5]);
+}
+
+#[test]
+pub fn test_malformed_keywords_do_not_panic() {
+    let read = |keyword: &str, value: Option<&str>| {
+        let mut keywords = common::list_mode_keywords("I", "1,2", 2, &[("FSC-A", "16", "1024"), ("SSC-A", "16", "1024")]);
+        match value {
+            Some(value) => common::set_keyword(&mut keywords, keyword, value),
+            None => common::remove_keyword(&mut keywords, keyword)
+        }
+        read_fcs_from_bytes(&common::build_fcs("FCS3.1", b'|', &keywords, &[1, 0, 2, 0, 3, 0, 4, 0]))
+    };
+
+    for (keyword, value) in [
+        ("$DATATYPE", None), ("$PAR", None), ("$TOT", None), ("$BYTEORD", None), ("$P2N", None),
+        ("$DATATYPE", Some("X")), ("$TOT", Some("many")), ("$PAR", Some("18446744073709551615")),
+        ("$MODE", Some("C")),
+    ] {
+        assert!(read(keyword, value).is_err(), "{} {:?} should be rejected", keyword, value);
+    }
+
+    // an empty data set is not an error
+    let flowdata = read("$TOT", Some("0")).unwrap();
+    assert!(flowdata.data.iter().all(|parameter| parameter.events.is_empty()));
+}
+
+#[test]
+pub fn test_zero_begindata_uses_header_offsets() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("I", "1,2", 2, &[("FSC-A", "16", "1024")]);
+    common::set_keyword(&mut keywords, "$BEGINDATA", "0");
+    common::set_keyword(&mut keywords, "$ENDDATA", "0");
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &[5, 0, 6, 0]);
+
+    let flowdata = read_fcs_from_bytes(&bytes)?;
+    assert_eq!(flowdata.data[0].events, vec![5.0, 6.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_blank_header_offsets() -> Result<(), FcsError> {
+    let keywords = common::list_mode_keywords("I", "1,2", 2, &[("FSC-A", "16", "1024")]);
+    let mut bytes = common::build_fcs("FCS3.1", b'|', &keywords, &[5, 0, 6, 0]);
+    // blank data and analysis offsets, and a zero padded text offset
+    bytes[26..58].fill(b' ');
+    bytes[10..18].copy_from_slice(b"00000058");
+
+    let flowdata = read_fcs_from_bytes(&bytes)?;
+    assert_eq!(flowdata.data[0].events, vec![5.0, 6.0]);
+    assert_eq!(flowdata.header().txt_start, 58);
+    assert_eq!(flowdata.header().analysis_start, 0);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_big_endian_integers() -> Result<(), FcsError> {
+    // integers in the opposite byte order of the host skip the bulk fast path and are decoded
+    // value by value, in every integer width
+    let values: Vec<u64> = (0..45).map(|i| i * 0x01010101 + 7).collect();
+    for (bits, byte_order) in [("16", "2,1"), ("32", "4,3,2,1"), ("64", "8,7,6,5,4,3,2,1")] {
+        let width = bits.parse::<usize>().unwrap() / 8;
+        let order: Vec<usize> = (1..=width).rev().collect();
+        let mask = if width == 8 { u64::MAX } else { (1u64 << (8 * width)) - 1 };
+        let params = [("FSC-H", bits, "18446744073709551615"), ("SSC-H", bits, "18446744073709551615"), ("FL1-H", bits, "18446744073709551615")];
+        let keywords = common::list_mode_keywords("I", byte_order, values.len() / 3, &params);
+        let data: Vec<u8> = values.iter().flat_map(|&v| common::int_bytes(v & mask, &order)).collect();
+        let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &data);
+        let path = common::write_fixture(&format!("big_endian_integers_{}", bits), &bytes);
+
+        let flowdata = read_fcs(&path)?;
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(flowdata.data[i % 3].events[i / 3], (value & mask) as f64, "{} bit value {}", bits, i);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_fcs_3_0_ascii_file() -> Result<(), FcsError> {
+    // zero padded fixed width values with a line break after the data segment
+    let flowdata = read_fcs(FORMAT_3_0_ASCII_TESTFILE)?;
+    assert_eq!(flowdata.metadata.values.get("$DATATYPE").unwrap(), "A");
+    assert_eq!(flowdata.data[0].events, vec![12.0, 1024.0, 7.0, 500.0]);
+    assert_eq!(flowdata.data[1].events, vec![3071.0, 0.0, 88.0, 501.0]);
+    assert_eq!(flowdata.data[2].events, vec![5.0, 999.0, 1023.0, 502.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_fcs_2_0_big_endian_file() -> Result<(), FcsError> {
+    // 16-bit integers with a four byte $BYTEORD, the text segment at byte 256, the data segment
+    // aligned after padding and its offsets only in the header
+    let flowdata = read_fcs(FORMAT_2_0_BIG_ENDIAN_TESTFILE)?;
+    assert_eq!(flowdata.metadata.header.version, "FCS2.0");
+    assert_eq!(flowdata.metadata.header.txt_start, 256);
+    assert_eq!(flowdata.metadata.header.data_start, 768);
+    assert!(!flowdata.metadata.values.contains_key("$BEGINDATA"));
+    assert_eq!(flowdata.metadata.values.get("APPLICATION").unwrap(), "CellQuest Pro");
+    assert_eq!(flowdata.data[0].events, vec![312.0, 1023.0, 0.0, 640.0, 87.0]);
+    assert_eq!(flowdata.data[1].events, vec![88.0, 515.0, 1.0, 200.0, 1023.0]);
+    assert_eq!(flowdata.data[2].events, vec![1.0, 17.0, 512.0, 1000.0, 256.0]);
+    assert_eq!(flowdata.data[3].events, vec![240.0, 900.0, 3.0, 77.0, 512.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_mixed_float_byte_orders() -> Result<(), FcsError> {
+    // "3,4,1,2" stores the 16-bit words of each float big endian first, little endian within
+    let values = [1.5f32, -2.25, 1.0e-3, 65504.0];
+    let data: Vec<u8> = values.iter()
+        .flat_map(|v| common::int_bytes(v.to_bits() as u64, &[3, 4, 1, 2]))
+        .collect();
+    let keywords = common::list_mode_keywords("F", "3,4,1,2", 2, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+    let flowdata = read_fcs_from_bytes(&common::build_fcs("FCS3.0", b'|', &keywords, &data))?;
+    assert_eq!(flowdata.data[0].events, vec![1.5, 1.0e-3f32 as f64]);
+    assert_eq!(flowdata.data[1].events, vec![-2.25, 65504.0]);
+
+    // orders that are not a permutation, or that cannot be widened to doubles, are errors
+    for (datatype, byte_order, bits) in [("F", "1,3,2", "32"), ("F", "1,2,3,5", "32"), ("D", "3,4,1,2", "64")] {
+        let keywords = common::list_mode_keywords(datatype, byte_order, 1, &[("FSC-A", bits, "1024")]);
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &[0; 8]);
+        match read_fcs_from_bytes(&bytes) {
+            Err(FcsError::UnsupportedByteOrder { byte_order: order, .. }) => assert_eq!(order, byte_order),
+            other => panic!("expected UnsupportedByteOrder for {}, got {:?}", byte_order, other.map(|_| ()))
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_mixed_formats_bulk_read() -> Result<(), FcsError> {
+    // a placeholder parameter and mixed widths take the column by column decoding path
+    let total_events = 70_000u64;
+    let params = [("FSC-H", "16", "65536"), ("EMPTY", "0", "0"), ("SSC-H", "32", "4294967296"), ("FL1-H", "8", "256")];
+    let keywords = common::list_mode_keywords("I", "4,3,2,1", total_events as usize, &params);
+    let mut data = Vec::new();
+    for event in 0..total_events {
+        data.extend(common::int_bytes(event % 65536, &[2, 1]));
+        data.extend(common::int_bytes(event * 3, &[4, 3, 2, 1]));
+        data.extend(common::int_bytes(event % 256, &[1]));
+    }
+    let flowdata = read_fcs_from_bytes(&common::build_fcs("FCS3.1", b'|', &keywords, &data))?;
+
+    for event in [0, 1, 255, 256, 65535, 65536, 69_999] {
+        let row: Vec<f64> = flowdata.data.iter().map(|parameter| parameter.events[event as usize]).collect();
+        assert_eq!(row, vec![(event % 65536) as f64, 0.0, (event * 3) as f64, (event % 256) as f64]);
+    }
+    assert!(flowdata.data.iter().all(|parameter| parameter.events.len() == total_events as usize));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_preserve_multi_line_comment() -> Result<(), FcsError> {
+    let comment = "\n  sorted by: A. Operator\r\n  stain: CD4 / CD8\r\n\n";
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$COM", comment);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("multi_line_comment", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values["$COM"], "sorted by: A. Operator\n  stain: CD4 / CD8");
+
+    let options = FcsReadOptions { preserve_comment: true, ..FcsReadOptions::default() };
+    let flowdata = read_fcs_with_options(&path, &options)?;
+    assert_eq!(flowdata.metadata.values["$COM"], comment);
+    assert_eq!(flowdata.metadata.values["$P1N"], "FSC-A");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_estimated_memory() -> Result<(), FcsError> {
+    let estimate = estimated_memory(FORMAT_3_0_TESTFILE)?;
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+
+    let events: usize = flowdata.data.iter().map(|parameter| parameter.events.capacity() * 8).sum();
+    let names: usize = flowdata.data.iter().map(|parameter| parameter.id.capacity()).sum();
+    let keywords: usize = flowdata.metadata.values.iter().map(|(keyword, value)| 2 * keyword.len() + value.len()).sum();
+    let allocated = events + names + keywords;
+
+    // the events dominate, the estimate is close to what was allocated
+    assert!(estimate >= events);
+    assert!(estimate as f64 >= 0.9 * allocated as f64, "{} < {}", estimate, allocated);
+    assert!((estimate as f64) < 1.1 * allocated as f64, "{} > {}", estimate, allocated);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_header_analysis_offsets() -> Result<(), FcsError> {
+    // an analysis segment after the data segment, its offsets only given in the text segment
+    let analysis = b"/GATE1/CD4+/";
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$BEGINANALYSIS", "00000000");
+    common::set_keyword(&mut keywords, "$ENDANALYSIS", "00000000");
+    let analysis_start = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0])).len();
+    let analysis_end = analysis_start + analysis.len() - 1;
+    common::set_keyword(&mut keywords, "$BEGINANALYSIS", &format!("{:08}", analysis_start));
+    common::set_keyword(&mut keywords, "$ENDANALYSIS", &format!("{:08}", analysis_end));
+    let mut bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+    bytes.extend_from_slice(analysis);
+
+    let flowdata = read_fcs_from_bytes(&bytes)?;
+    let header = flowdata.header();
+    assert_eq!((header.analysis_start, header.analysis_end), (analysis_start as u64, analysis_end as u64));
+    assert_eq!(&bytes[header.analysis_start as usize..=header.analysis_end as usize], analysis);
+    assert_eq!(flowdata.data[0].events, vec![1.0]);
+
+    Ok(())
+}
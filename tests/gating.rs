@@ -0,0 +1,194 @@
+use flowfairy_api::{FcsError, FlowData, Metadata, Parameter, Window};
+
+fn build_flowdata(keywords: &[(&str, &str)], data: Vec<Parameter>) -> FlowData {
+    let mut metadata = Metadata::default();
+    for (keyword, value) in keywords {
+        metadata.insert(keyword, value);
+    }
+    FlowData { metadata, data }
+}
+
+#[test]
+pub fn test_region_on_log_scale() -> Result<(), FcsError> {
+    let flowdata = build_flowdata(
+        &[
+            ("$P1N", "FL1-A"), ("$P1R", "262144"), ("$P1D", "Logarithmic,5,1"),
+            ("$P2N", "FSC-A"), ("$P2R", "262144"),
+            ("$R1I", "1"), ("$R1W", "1,2"),
+            ("$R2I", "2,1"), ("$R2W", "0,0;100,0;100,2;0,2"),
+        ],
+        vec![
+            Parameter { id: "FL1-A".to_string(), events: vec![5.0, 50.0, 500.0, 10.0, 80.0] },
+            Parameter { id: "FSC-A".to_string(), events: vec![10.0, 10.0, 10.0, 200.0, 50.0] },
+        ]
+    );
+
+    let regions = flowdata.regions()?;
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].window, Window::Interval(1.0, 2.0));
+
+    // the window spans 10 to 100 on the linear data
+    assert_eq!(regions[0].apply(&flowdata)?, vec![false, true, false, true, true]);
+    // FSC-A is linear, FL1-A is compared on its log scale
+    assert_eq!(regions[1].parameters, vec![1, 0]);
+    assert_eq!(regions[1].apply(&flowdata)?, vec![true, true, false, false, true]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_region_on_log_amplified_parameter() -> Result<(), FcsError> {
+    // channels are linearized with $PnE before the $PnD log display scale is applied
+    let flowdata = build_flowdata(
+        &[
+            ("$P1N", "FL1-H"), ("$P1R", "1024"), ("$P1E", "4,1"), ("$P1D", "Logarithmic,4,1"),
+            ("$P2N", "FL2-H"), ("$P2R", "1024"), ("$P2E", "4,1"),
+            ("$R1I", "1"), ("$R1W", "1.5,2.5"),
+            ("$R2I", "2"), ("$R2W", "50,500"),
+        ],
+        vec![
+            Parameter { id: "FL1-H".to_string(), events: vec![0.0, 512.0, 768.0, 1024.0] },
+            Parameter { id: "FL2-H".to_string(), events: vec![0.0, 512.0, 768.0, 1024.0] },
+        ]
+    );
+
+    // the channels are 1, 100, 1000 and 10000 on the linear scale
+    let regions = flowdata.regions()?;
+    assert_eq!(regions[0].apply(&flowdata)?, vec![false, true, false, false]);
+    assert_eq!(regions[1].apply(&flowdata)?, vec![false, true, false, false]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_invalid_region() {
+    let flowdata = build_flowdata(
+        &[("$P1N", "FL1-A"), ("$R1I", "1"), ("$R1W", "1;2")],
+        vec![Parameter { id: "FL1-A".to_string(), events: vec![1.0] }]
+    );
+    assert!(matches!(flowdata.regions(), Err(FcsError::InvalidKeywordValue { .. })));
+
+    let flowdata = build_flowdata(
+        &[("$P1N", "FL1-A"), ("$R1I", "2"), ("$R1W", "1,2")],
+        vec![Parameter { id: "FL1-A".to_string(), events: vec![1.0] }]
+    );
+    assert!(matches!(flowdata.regions(), Err(FcsError::InvalidKeywordValue { .. })));
+}
+
+#[test]
+pub fn test_gate_percent_of_parent() -> Result<(), FcsError> {
+    let flowdata = build_flowdata(
+        &[("$P1N", "FSC-A"), ("$P2N", "SSC-A"), ("$TOT", "8")],
+        vec![
+            Parameter { id: "FSC-A".to_string(), events: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0] },
+            Parameter { id: "SSC-A".to_string(), events: vec![1.0, 1.0, 5.0, 5.0, 5.0, 9.0, 5.0, 5.0] },
+        ]
+    );
+
+    let result = flowdata.gate_rectangle_with_stats("FSC-A", "SSC-A", (2.0, 7.0), (4.0, 6.0))?;
+    assert_eq!(result.input_events, 8);
+    assert_eq!(result.output_events, 4);
+    assert_eq!(result.percent, 50.0);
+    assert_eq!(result.data.data[0].events, vec![3.0, 4.0, 5.0, 7.0]);
+    assert_eq!(result.data.metadata.values.get("$TOT").unwrap(), "4");
+
+    let triangle = [(0.0, 0.0), (11.0, 0.0), (0.0, 11.0)];
+    let result = flowdata.gate_polygon_with_stats("FSC-A", "SSC-A", &triangle)?;
+    let surviving = result.data.data[0].events.len();
+    assert_eq!(surviving, 5);
+    assert_eq!(result.percent, 100.0 * surviving as f64 / 8.0);
+
+    let gated = flowdata.gate_polygon("FSC-A", "SSC-A", &triangle)?;
+    assert_eq!(gated.data[1].events, result.data.data[1].events);
+    assert!(matches!(flowdata.gate_rectangle("FSC-A", "FL1-A", (0.0, 1.0), (0.0, 1.0)), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_parameter_where() -> Result<(), FcsError> {
+    let flowdata = build_flowdata(
+        &[("$P1N", "CD3"), ("$P2N", "CD19")],
+        vec![
+            Parameter { id: "CD3".to_string(), events: vec![900.0, 15.0, 870.0, 20.0, 910.0] },
+            Parameter { id: "CD19".to_string(), events: vec![10.0, 800.0, 100.0, 950.0, -5.0] },
+        ]
+    );
+
+    // CD3 values where CD19 is low
+    assert_eq!(flowdata.parameter_where("CD3", "CD19", -10.0, 100.0)?, vec![900.0, 870.0, 910.0]);
+    assert_eq!(flowdata.parameter_where("CD19", "CD19", 500.0, 1000.0)?, vec![800.0, 950.0]);
+    assert!(flowdata.parameter_where("CD3", "CD19", 2000.0, 3000.0)?.is_empty());
+    assert!(matches!(flowdata.parameter_where("CD4", "CD19", 0.0, 1.0), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_suggest_gates() -> Result<(), FcsError> {
+    // two well separated clusters, the second on a much larger y scale
+    let mut fsc = Vec::new();
+    let mut ssc = Vec::new();
+    for i in 0..50 {
+        let jitter = (i % 7) as f64;
+        fsc.push(100.0 + jitter);
+        ssc.push(1000.0 + 10.0 * jitter);
+        fsc.push(900.0 - jitter);
+        ssc.push(50_000.0 - 100.0 * jitter);
+    }
+    let flowdata = build_flowdata(&[], vec![
+        Parameter { id: "FSC-A".to_string(), events: fsc },
+        Parameter { id: "SSC-A".to_string(), events: ssc },
+    ]);
+
+    let gates = flowdata.suggest_gates("FSC-A", "SSC-A", 2)?;
+    assert_eq!(gates.len(), 2);
+    assert_eq!(gates[0].x_range, (100.0, 106.0));
+    assert_eq!(gates[0].y_range, (1000.0, 1060.0));
+    assert_eq!(gates[1].x_range, (894.0, 900.0));
+    assert_eq!(gates[1].y_range, (49_400.0, 50_000.0));
+    assert_eq!(gates[0].events + gates[1].events, 100);
+    assert_eq!(gates[0].apply(&flowdata)?.data[0].events.len(), 50);
+
+    assert!(matches!(flowdata.suggest_gates("FSC-A", "SSC-A", 0), Err(FcsError::InvalidArgument(_))));
+    assert!(matches!(flowdata.suggest_gates("FSC-A", "FL1-A", 2), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_stride() -> Result<(), FcsError> {
+    let flowdata = build_flowdata(&[("$TOT", "100")], vec![
+        Parameter { id: "FSC-A".to_string(), events: (0..100).map(|i| i as f64).collect() },
+        Parameter { id: "SSC-A".to_string(), events: (0..100).map(|i| -i as f64).collect() },
+    ]);
+
+    let strided = flowdata.stride(10)?;
+    assert_eq!(strided.data[0].events, (0..10).map(|i| 10.0 * i as f64).collect::<Vec<f64>>());
+    assert_eq!(strided.data[1].events, (0..10).map(|i| -10.0 * i as f64).collect::<Vec<f64>>());
+    assert_eq!(strided.metadata.values["$TOT"], "10");
+
+    assert_eq!(flowdata.stride(1)?.data[0].events, flowdata.data[0].events);
+    assert_eq!(flowdata.stride(33)?.data[0].events, vec![0.0, 33.0, 66.0, 99.0]);
+    assert!(matches!(flowdata.stride(0), Err(FcsError::InvalidArgument(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_apply_mask() -> Result<(), FcsError> {
+    let flowdata = build_flowdata(&[("$TOT", "6")], vec![
+        Parameter { id: "FSC-A".to_string(), events: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0] },
+        Parameter { id: "SSC-A".to_string(), events: vec![10.0, 20.0, 30.0, 40.0, 50.0, 60.0] },
+    ]);
+
+    let mask: Vec<bool> = (0..6).map(|i| i % 2 == 0).collect();
+    let masked = flowdata.apply_mask(&mask)?;
+    assert_eq!(masked.data[0].events, vec![1.0, 3.0, 5.0]);
+    assert_eq!(masked.data[1].events, vec![10.0, 30.0, 50.0]);
+    assert_eq!(masked.metadata.values["$TOT"], "3");
+
+    assert!(matches!(flowdata.apply_mask(&mask[..5]), Err(FcsError::InvalidArgument(_))));
+
+    Ok(())
+}
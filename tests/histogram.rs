@@ -0,0 +1,50 @@
+mod common;
+
+use std::io::Cursor;
+use flowfairy_api::{read_fcs, read_histogram, read_histogram_from, FcsError, HistogramMode};
+
+#[test]
+pub fn test_uncorrelated_histograms() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("I", "4,3,2,1", 10, &[("FSC-H", "16", "4"), ("SSC-H", "16", "3")]);
+    common::set_keyword(&mut keywords, "$MODE", "U");
+    let data: Vec<u8> = [1u64, 2, 3, 4, 5, 0, 5].iter().flat_map(|&c| common::int_bytes(c, &[2, 1])).collect();
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &data);
+    let path = common::write_fixture("histogram_uncorrelated", &bytes);
+
+    let histogram = read_histogram(&path)?;
+    assert_eq!(histogram.mode, HistogramMode::Uncorrelated);
+    assert_eq!(histogram.parameters, vec!["FSC-H", "SSC-H"]);
+    assert_eq!(histogram.bins, vec![4, 3]);
+    assert_eq!(histogram.counts, vec![vec![1.0, 2.0, 3.0, 4.0], vec![5.0, 0.0, 5.0]]);
+
+    // list mode readers refuse histogram data instead of misreading it
+    assert!(matches!(read_fcs(&path), Err(FcsError::InvalidKeywordValue { keyword, .. }) if keyword == "$MODE"));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_correlated_histogram() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("I", "1,2,3,4", 21, &[("FSC-H", "32", "2"), ("SSC-H", "32", "3")]);
+    common::set_keyword(&mut keywords, "$MODE", "C");
+    let data: Vec<u8> = (1..=6u64).flat_map(|c| common::int_bytes(c, &[1, 2, 3, 4])).collect();
+    let bytes = common::build_fcs("FCS2.0", b'|', &keywords, &data);
+
+    let histogram = read_histogram_from(Cursor::new(&bytes))?;
+    assert_eq!(histogram.mode, HistogramMode::Correlated);
+    assert_eq!(histogram.bins, vec![2, 3]);
+    assert_eq!(histogram.counts, vec![vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]]);
+
+    // more bins than the data segment holds
+    let mut keywords = keywords.clone();
+    common::set_keyword(&mut keywords, "$P2R", "4096");
+    let bytes = common::build_fcs("FCS2.0", b'|', &keywords, &data);
+    assert!(matches!(read_histogram_from(Cursor::new(&bytes)), Err(FcsError::TruncatedData { expected: 8192, available: 6 })));
+
+    // list mode data is not a histogram
+    let keywords = common::list_mode_keywords("I", "1,2,3,4", 1, &[("FSC-H", "32", "2")]);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &[0; 4]);
+    assert!(matches!(read_histogram_from(Cursor::new(&bytes)), Err(FcsError::InvalidKeywordValue { .. })));
+
+    Ok(())
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use flowfairy_api::{read_fcs, FcsError, PanelEntry, SampleInfo};
+
+const FORMAT_3_0_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_3_0.fcs");
+
+#[test]
+pub fn test_cell_subsets() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$CSMODE", "3");
+    common::set_keyword(&mut keywords, "$CSVBITS", "8");
+    common::set_keyword(&mut keywords, "$CSV1FLAG", "CD4+");
+    common::set_keyword(&mut keywords, "$CSV2FLAG", "CD8+");
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("cell_subsets", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.cell_subsets(), Some(vec!["CD4+".to_string(), "CD8+".to_string(), String::new()]));
+
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    assert_eq!(flowdata.cell_subsets(), None);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_abort_and_lost_counts() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$ABRT", "1523");
+    common::set_keyword(&mut keywords, "$LOST", " 42 ");
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("abort_lost_counts", &bytes);
+
+    let metadata = read_fcs(&path)?.metadata;
+    assert_eq!(metadata.abort_count(), Some(1523));
+    assert_eq!(metadata.lost_count(), Some(42));
+
+    let metadata = read_fcs(FORMAT_3_0_TESTFILE)?.metadata;
+    assert_eq!(metadata.abort_count(), None);
+    assert_eq!(metadata.lost_count(), None);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_panel() -> Result<(), FcsError> {
+    let panel = read_fcs(FORMAT_3_0_TESTFILE)?.panel();
+    let detectors: Vec<&str> = panel.iter().map(|e| e.detector.as_str()).collect();
+    assert_eq!(detectors, ["TIME", "FSC-A", "FSC-H", "FSC-W", "SSC-A", "FL1-A"]);
+    assert_eq!(panel[5], PanelEntry {
+        detector: "FL1-A".to_string(),
+        marker: Some("Phenovue493-A".to_string()),
+        laser: None,
+        filter: None,
+        detector_type: None
+    });
+
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FL1-A", "32", "1024"), ("FL2-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$P1S", "CD3");
+    common::set_keyword(&mut keywords, "$P1L", "488,405");
+    common::set_keyword(&mut keywords, "$P1F", "530/30");
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    let path = common::write_fixture("panel", &bytes);
+
+    let panel = read_fcs(&path)?.panel();
+    assert_eq!(panel[0].marker.as_deref(), Some("CD3"));
+    assert_eq!(panel[0].laser, Some(488.0));
+    assert_eq!(panel[0].filter.as_deref(), Some("530/30"));
+    assert_eq!(panel[1], PanelEntry { detector: "FL2-A".to_string(), marker: None, laser: None, filter: None, detector_type: None });
+
+    Ok(())
+}
+
+#[test]
+pub fn test_detector_type() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FL1-A", "32", "1024"), ("FL2-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$P1T", "PMT");
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0, 2.0]));
+    let path = common::write_fixture("detector_type", &bytes);
+
+    let panel = read_fcs(&path)?.panel();
+    assert_eq!(panel[0].detector_type.as_deref(), Some("PMT"));
+    assert_eq!(panel[1].detector_type, None);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_sample_info() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$CELLS", "PBMC");
+    common::set_keyword(&mut keywords, "$SRC", "donor 17");
+    common::set_keyword(&mut keywords, "$SMNO", "tube 3");
+    common::set_keyword(&mut keywords, "$PROJ", "T cell panel");
+    common::set_keyword(&mut keywords, "$EXP", " ");
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("sample_info", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.sample_info(), SampleInfo {
+        cells: Some("PBMC".to_string()),
+        source: Some("donor 17".to_string()),
+        specimen: Some("tube 3".to_string()),
+        project: Some("T cell panel".to_string()),
+        experiment: None
+    });
+
+    Ok(())
+}
+
+#[test]
+pub fn test_timestep() -> Result<(), FcsError> {
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    assert_eq!(flowdata.metadata.timestep()?, Some(1.0));
+
+    flowdata.metadata.insert("$TIMESTEP", " 0.01 ");
+    assert_eq!(flowdata.metadata.timestep()?, Some(0.01));
+    flowdata.metadata.insert("$TIMESTEP", "10 ms");
+    assert!(matches!(flowdata.metadata.timestep(), Err(FcsError::InvalidKeywordValue { .. })));
+    flowdata.metadata.values.remove("$TIMESTEP");
+    assert_eq!(flowdata.metadata.timestep()?, None);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_validate_ranges() -> Result<(), FcsError> {
+    let keywords = common::list_mode_keywords("I", "1,2,3,4", 1, &[("FSC-H", "8", "1024"), ("SSC-H", "16", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &[7, 1, 0]);
+    let path = common::write_fixture("range_exceeds_bit_width", &bytes);
+
+    // reading is not strict, the check is opt-in
+    let flowdata = read_fcs(&path)?;
+    match flowdata.metadata.validate_ranges() {
+        Err(FcsError::RangeExceedsBitWidth { parameter }) => assert_eq!(parameter, "FSC-H"),
+        other => panic!("expected RangeExceedsBitWidth, got {:?}", other)
+    }
+
+    // 2^$PnB itself is a valid range
+    let keywords = common::list_mode_keywords("I", "1,2,3,4", 1, &[("FSC-H", "8", "256")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &[7]);
+    let path = common::write_fixture("range_at_bit_width", &bytes);
+    assert!(read_fcs(&path)?.metadata.validate_ranges().is_ok());
+
+    Ok(())
+}
+
+#[test]
+pub fn test_flow_rate() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$FLOWRATE", "12.5");
+    let bytes = common::build_fcs("FCS3.2", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("flow_rate", &bytes);
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.flow_rate(), Some(12.5));
+
+    // vendor keywords pass validation when the file is read
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "#SAMPLE FLOW RATE", "30 uL/min");
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&[1.0]));
+    let path = common::write_fixture("vendor_flow_rate", &bytes);
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.metadata.values["#SAMPLE FLOW RATE"], "30 uL/min");
+    assert_eq!(flowdata.metadata.flow_rate(), Some(30.0));
+
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    assert_eq!(flowdata.metadata.flow_rate(), None);
+    flowdata.metadata.insert("FLOW RATE", "60 uL/min");
+    assert_eq!(flowdata.metadata.flow_rate(), Some(60.0));
+    flowdata.metadata.insert("FLOW RATE", "fast");
+    assert_eq!(flowdata.metadata.flow_rate(), None);
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use flowfairy_api::{read_fcs, write_fcs, FcsError, FlowData, Metadata, Parameter};
+
+fn build_flowdata(columns: &[(&str, Vec<f64>)]) -> FlowData {
+    FlowData {
+        metadata: Metadata::default(),
+        data: columns.iter()
+            .map(|(id, events)| Parameter { id: id.to_string(), events: events.clone() })
+            .collect()
+    }
+}
+
+#[test]
+pub fn test_to_column_major() -> Result<(), FcsError> {
+    let flowdata = build_flowdata(&[
+        ("FSC-A", vec![1.0, 2.0, 3.0]),
+        ("SSC-A", vec![10.0, 20.0, 30.0]),
+    ]);
+
+    let (rows, cols) = flowdata.shape();
+    assert_eq!((rows, cols), (3, 2));
+    let buffer = flowdata.to_column_major()?;
+    assert_eq!(buffer, vec![1.0, 2.0, 3.0, 10.0, 20.0, 30.0]);
+    for i in 0..rows {
+        for j in 0..cols {
+            assert_eq!(buffer[j * rows + i], flowdata.data[j].events[i]);
+        }
+    }
+
+    let ragged = build_flowdata(&[("FSC-A", vec![1.0]), ("SSC-A", vec![])]);
+    assert!(matches!(ragged.to_column_major(), Err(FcsError::InconsistentEventCounts)));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_join_parameters() -> Result<(), FcsError> {
+    let read = |name: &str, params: &[(&str, &str, &str)], events: &[f32]| {
+        let keywords = common::list_mode_keywords("F", "1,2,3,4", 2, params);
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(events));
+        read_fcs(&common::write_fixture(name, &bytes))
+    };
+    let mut scatter = read("join_scatter", &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024"), ("Time", "32", "4096")], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0])?;
+    let fluorescence = read("join_fluorescence", &[("FL1-A", "32", "512"), ("FL2-A", "32", "512"), ("FL3-A", "32", "256")], &[7.0, 8.0, 9.0, 10.0, 11.0, 12.0])?;
+
+    scatter.join_parameters(&fluorescence)?;
+    assert_eq!(scatter.shape(), (2, 6));
+    assert_eq!(scatter.metadata.values["$PAR"], "6");
+    assert_eq!(scatter.metadata.values["$P4N"], "FL1-A");
+    assert_eq!(scatter.metadata.values["$P6N"], "FL3-A");
+    assert_eq!(scatter.metadata.values["$P6R"], "256");
+    assert_eq!(scatter.metadata.values["$P3R"], "4096");
+    assert_eq!(scatter.data[5].events, vec![9.0, 12.0]);
+
+    // the joined data set can be written and read back
+    let path = std::env::temp_dir().join("flowfairy_join_parameters.fcs");
+    write_fcs(&scatter, path.to_str().unwrap())?;
+    assert_eq!(read_fcs(path.to_str().unwrap())?.data[4].events, vec![8.0, 11.0]);
+
+    // names must be unique and event counts equal
+    assert!(matches!(scatter.join_parameters(&fluorescence), Err(FcsError::DuplicateParameter(_))));
+    let short = build_flowdata(&[("FL4-A", vec![1.0])]);
+    assert!(matches!(scatter.join_parameters(&short), Err(FcsError::InconsistentEventCounts)));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_events() {
+    let flowdata = build_flowdata(&[
+        ("FSC-A", vec![1.0, 2.0, 3.0]),
+        ("SSC-A", vec![10.0, 20.0, 30.0]),
+        ("FL1-A", vec![100.0, 200.0, 300.0]),
+    ]);
+
+    let first: Vec<f64> = flowdata.data.iter().map(|parameter| parameter.events[0]).collect();
+    assert_eq!(flowdata.event(0), Some(first.clone()));
+    assert_eq!(flowdata.event(2), Some(vec![3.0, 30.0, 300.0]));
+    assert_eq!(flowdata.event(3), None);
+
+    let rows: Vec<Vec<f64>> = flowdata.events().collect();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0], first);
+    assert_eq!(rows[1], vec![2.0, 20.0, 200.0]);
+
+    let ragged = build_flowdata(&[("FSC-A", vec![1.0, 2.0]), ("SSC-A", vec![10.0])]);
+    assert_eq!(ragged.events().count(), 1);
+}
@@ -0,0 +1,40 @@
+use flowfairy_api::{summarize_wells, FlowData, Metadata, Parameter};
+
+fn well_flowdata(well: Option<&str>, fsc: Vec<f64>) -> FlowData {
+    let mut metadata = Metadata::default();
+    if let Some(well) = well {
+        metadata.insert("$WELLID", well);
+    }
+    let ssc = fsc.iter().map(|v| 2.0 * v).collect();
+    FlowData {
+        metadata,
+        data: vec![
+            Parameter { id: "FSC-A".to_string(), events: fsc },
+            Parameter { id: "SSC-A".to_string(), events: ssc },
+        ]
+    }
+}
+
+#[test]
+pub fn test_summarize_wells() {
+    let files = vec![
+        ("a01_1.fcs".to_string(), well_flowdata(Some("A01"), vec![1.0, 2.0, 3.0])),
+        ("a02.fcs".to_string(), well_flowdata(Some("A02"), vec![10.0, 20.0])),
+        ("a01_2.fcs".to_string(), well_flowdata(Some(" A01 "), vec![4.0, 5.0])),
+        ("unknown.fcs".to_string(), well_flowdata(None, vec![100.0])),
+    ];
+
+    let wells = summarize_wells(&files);
+    assert_eq!(wells.len(), 2);
+
+    let a01 = &wells["A01"];
+    assert_eq!(a01.files, vec!["a01_1.fcs", "a01_2.fcs"]);
+    assert_eq!(a01.events, 5);
+    assert_eq!(a01.medians["FSC-A"], 3.0);
+    assert_eq!(a01.medians["SSC-A"], 6.0);
+
+    let a02 = &wells["A02"];
+    assert_eq!(a02.files, vec!["a02.fcs"]);
+    assert_eq!(a02.events, 2);
+    assert_eq!(a02.medians["FSC-A"], 15.0);
+}
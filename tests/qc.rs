@@ -0,0 +1,66 @@
+mod common;
+
+use flowfairy_api::{read_fcs, FcsError, FlowData, Metadata, Parameter};
+
+#[test]
+pub fn test_count_saturated() -> Result<(), FcsError> {
+    let events = [
+        12.0, 1023.0,
+        1023.0, 1023.0,
+        1022.5, 500.0,
+        40.0, 1022.0,
+    ];
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 4, &[("FL1-A", "32", "1024"), ("FL2-A", "32", "1024")]);
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &common::f32_le(&events));
+    let path = common::write_fixture("count_saturated", &bytes);
+
+    let flowdata = read_fcs(&path)?;
+    let counts = flowdata.count_saturated(&["FL1-A", "FL2-A"])?;
+    assert_eq!(counts["FL1-A"], 1);
+    assert_eq!(counts["FL2-A"], 2);
+
+    assert!(matches!(flowdata.count_saturated(&["FL3-A"]), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_time_bins() -> Result<(), FcsError> {
+    // time units of 10 ms over 2.5 s, one event every 50 ms
+    let mut metadata = Metadata::default();
+    metadata.insert("$TIMESTEP", "0.01");
+    let time: Vec<f64> = (0..50).map(|i| 100.0 + 5.0 * i as f64).collect();
+    let flowdata = FlowData {
+        metadata,
+        data: vec![
+            Parameter { id: "FSC-A".to_string(), events: (0..50).map(|i| i as f64).collect() },
+            Parameter { id: "Time".to_string(), events: time },
+        ]
+    };
+
+    let bins = flowdata.time_bins(0.5)?;
+    assert_eq!(bins.len(), 5);
+    for (i, bin) in bins.iter().enumerate() {
+        assert_eq!(bin.data[1].events.len(), 10);
+        assert_eq!(bin.data[0].events[0], 10.0 * i as f64);
+        assert_eq!(bin.metadata.values.get("$TOT").unwrap(), "10");
+    }
+    assert_eq!(flowdata.time_bins(1.0)?.len(), 3);
+
+    assert!(matches!(flowdata.time_bins(0.0), Err(FcsError::InvalidArgument(_))));
+    assert!(matches!(flowdata.time_bins(f64::NAN), Err(FcsError::InvalidArgument(_))));
+    // intervals so small that the bins would not fit in memory
+    assert!(matches!(flowdata.time_bins(f64::MIN_POSITIVE), Err(FcsError::InvalidArgument(_))));
+    assert!(matches!(flowdata.time_bins(1e-6), Err(FcsError::InvalidArgument(_))));
+
+    let no_time = FlowData { metadata: flowdata.metadata.clone(), data: vec![] };
+    assert!(matches!(no_time.time_bins(1.0), Err(FcsError::ParameterNotFound(_))));
+
+    let mut invalid = flowdata;
+    for timestep in ["0", "-0.01", "NaN", "inf"] {
+        invalid.metadata.insert("$TIMESTEP", timestep);
+        assert!(matches!(invalid.time_bins(1.0), Err(FcsError::InvalidKeywordValue { .. })));
+    }
+
+    Ok(())
+}
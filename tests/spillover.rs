@@ -0,0 +1,364 @@
+use flowfairy_api::{compute_spillover, read_fcs, CompensatedEventReader, CompensationMatrix, FcsError, FlowData, Metadata, Parameter, Spillover};
+
+const FORMAT_3_0_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_3_0.fcs");
+
+// Build a single-stain control where `signal` is the primary parameter's events and every
+// parameter picks up `spill[i]` of that signal
+fn control(names: &[&str], spill: &[f64], signal: &[f64]) -> FlowData {
+    let data = names.iter().zip(spill.iter())
+        .map(|(name, coeff)| Parameter {
+            id: name.to_string(),
+            events: signal.iter().map(|s| s * coeff).collect()
+        })
+        .collect();
+    FlowData {
+        metadata: Metadata::default(),
+        data
+    }
+}
+
+#[test]
+pub fn test_compute_spillover() -> Result<(), FcsError> {
+    let names = ["FL1-A", "FL2-A", "FL3-A"];
+    let expected = [
+        [1.0, 0.2, 0.05],
+        [0.1, 1.0, 0.3],
+        [0.0, 0.15, 1.0],
+    ];
+    let signal: Vec<f64> = (0..200).map(|i| 100.0 + 10.0 * i as f64).collect();
+
+    let mut controls: Vec<(String, FlowData)> = names.iter().zip(expected.iter())
+        .map(|(name, spill)| (name.to_string(), control(&names, spill, &signal)))
+        .collect();
+    // a few outlier events should not move the median ratio
+    controls[0].1.data[1].events[0] = 1.0e6;
+    controls[0].1.data[1].events[1] = -1.0e6;
+
+    let spillover = compute_spillover(&controls)?;
+    assert_eq!(spillover.parameters, names);
+    for (i, from) in names.iter().enumerate() {
+        for (j, to) in names.iter().enumerate() {
+            let coeff = spillover.coefficient(from, to).unwrap();
+            assert!((coeff - expected[i][j]).abs() < 1e-9, "{} -> {}: {}", from, to, coeff);
+        }
+    }
+
+    let mut metadata = Metadata::default();
+    metadata.set_spillover(&spillover);
+    assert!(metadata.values.get("$SPILLOVER").unwrap().starts_with("3,FL1-A,FL2-A,FL3-A,1,0.2"));
+
+    // every control needs all of the control parameters
+    controls[2].1.data.remove(0);
+    assert!(matches!(compute_spillover(&controls), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_compensated_event_reader() -> Result<(), FcsError> {
+    let spillover = Spillover {
+        parameters: vec!["FL1-A".to_string(), "FL2-A".to_string()],
+        matrix: vec![1.0, 0.2, 0.1, 1.0]
+    };
+    let flowdata = FlowData {
+        metadata: Metadata::default(),
+        data: vec![
+            Parameter { id: "FSC-A".to_string(), events: vec![5.0, 6.0, 7.0] },
+            Parameter { id: "FL2-A".to_string(), events: vec![30.0, 120.0, 0.0] },
+            Parameter { id: "FL1-A".to_string(), events: vec![101.0, 12.0, 50.0] },
+        ]
+    };
+
+    let names: Vec<&str> = flowdata.data.iter().map(|p| p.id.as_str()).collect();
+    let matrix = CompensationMatrix::new(&spillover, &names)?;
+    let events = (0..3).map(|i| flowdata.data.iter().map(|p| p.events[i]).collect::<Vec<f64>>());
+    let streamed = CompensatedEventReader::new(events, matrix.clone()).collect::<Result<Vec<Vec<f64>>, FcsError>>()?;
+
+    let mut compensated = FlowData { metadata: Metadata::default(), data: flowdata.data };
+    compensated.compensate_with(&spillover)?;
+    for (i, event) in streamed.iter().enumerate() {
+        for (j, value) in event.iter().enumerate() {
+            assert!((value - compensated.data[j].events[i]).abs() < 1e-12);
+        }
+    }
+    // event 0 is FL1 100 spilling 20 into FL2, plus FL2 10 spilling 1 into FL1
+    assert!((streamed[0][2] - 100.0).abs() < 1e-9);
+    assert!((streamed[0][1] - 10.0).abs() < 1e-9);
+    assert_eq!(streamed[0][0], 5.0);
+
+    // an event without a value for FL1-A cannot be compensated
+    let mut short = vec![5.0, 30.0];
+    assert!(matches!(matrix.apply(&mut short), Err(FcsError::InvalidArgument(_))));
+    assert_eq!(short, vec![5.0, 30.0]);
+    let mut streamed = CompensatedEventReader::new(vec![vec![5.0, 30.0, 101.0], vec![5.0]].into_iter(), matrix);
+    assert!(streamed.next().unwrap().is_ok());
+    assert!(matches!(streamed.next(), Some(Err(FcsError::InvalidArgument(_)))));
+
+    // streaming the events of a real file matches compensating the whole data set
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let spillover = Spillover {
+        parameters: vec!["FL1-A".to_string(), "TIME".to_string()],
+        matrix: vec![1.0, 0.05, 0.02, 1.0]
+    };
+    let names: Vec<&str> = flowdata.data.iter().map(|p| p.id.as_str()).collect();
+    let matrix = CompensationMatrix::new(&spillover, &names)?;
+    let streamed = CompensatedEventReader::new(flowdata.events(), matrix).collect::<Result<Vec<Vec<f64>>, FcsError>>()?;
+
+    let mut compensated = read_fcs(FORMAT_3_0_TESTFILE)?;
+    compensated.compensate_with(&spillover)?;
+    assert_eq!(streamed.len(), compensated.data[0].events.len());
+    for (i, event) in streamed.iter().enumerate() {
+        for (j, value) in event.iter().enumerate() {
+            assert!((value - compensated.data[j].events[i]).abs() <= 1e-9 * value.abs().max(1.0));
+        }
+    }
+    assert_ne!(compensated.data[5].events, flowdata.data[5].events);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_spillover_is_invertible() {
+    let spillover = Spillover {
+        parameters: vec!["FL1-A".to_string(), "FL2-A".to_string()],
+        matrix: vec![1.0, 0.2, 0.1, 1.0]
+    };
+    assert!(spillover.is_invertible(1e-6));
+
+    // the second row is almost a multiple of the first
+    let near_singular = Spillover {
+        parameters: vec!["FL1-A".to_string(), "FL2-A".to_string()],
+        matrix: vec![1.0, 0.9, 1.0, 0.9000001]
+    };
+    assert!(!near_singular.is_invertible(1e-6));
+    assert!(near_singular.condition_number().unwrap() > 1e6);
+
+    let singular = Spillover {
+        parameters: vec!["FL1-A".to_string(), "FL2-A".to_string()],
+        matrix: vec![1.0, 0.5, 2.0, 1.0]
+    };
+    assert!(!singular.is_invertible(1e-6));
+    assert!(singular.condition_number().is_none());
+}
+
+#[test]
+pub fn test_identity_spillover() -> Result<(), FcsError> {
+    let names = vec!["FL1-A".to_string(), "FL2-A".to_string(), "FL3-A".to_string()];
+    let events = [vec![100.0, -3.5, 0.0], vec![12.0, 40.0, 1.0e5], vec![7.25, 0.5, 2.0]];
+    let mut flowdata = FlowData {
+        metadata: Metadata::default(),
+        data: names.iter().zip(events.iter())
+            .map(|(name, events)| Parameter { id: name.to_string(), events: events.clone() })
+            .collect()
+    };
+
+    let mut spillover = Spillover::identity(&names);
+    assert_eq!(spillover.coefficient("FL1-A", "FL1-A"), Some(1.0));
+    assert_eq!(spillover.coefficient("FL1-A", "FL2-A"), Some(0.0));
+    flowdata.compensate_with(&spillover)?;
+    for (parameter, events) in flowdata.data.iter().zip(events.iter()) {
+        assert_eq!(&parameter.events, events);
+    }
+
+    spillover.set("FL2-A", "FL3-A", 0.25)?;
+    assert_eq!(spillover.coefficient("FL2-A", "FL3-A"), Some(0.25));
+    assert_eq!(spillover.coefficient("FL3-A", "FL2-A"), Some(0.0));
+    assert!(matches!(spillover.set("FL2-A", "FL4-A", 0.1), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_compensation_sanity() -> Result<(), FcsError> {
+    // FL1 spills 20% into FL2, FL2 barely into FL1
+    let events = [vec![100.0, 2.0, 500.0, 51.0], vec![20.0, 40.0, 100.0, 210.0]];
+    let build = || FlowData {
+        metadata: Metadata::default(),
+        data: ["FL1-A", "FL2-A"].iter().zip(events.iter())
+            .map(|(name, events)| Parameter { id: name.to_string(), events: events.clone() })
+            .collect()
+    };
+    let original = build();
+    let names = vec!["FL1-A".to_string(), "FL2-A".to_string()];
+
+    let mut spillover = Spillover::identity(&names);
+    spillover.set("FL1-A", "FL2-A", 0.2)?;
+    spillover.set("FL2-A", "FL1-A", 0.01)?;
+    let mut compensated = build();
+    compensated.compensate_with(&spillover)?;
+    compensated.compensation_sanity(&original, 2.0)?;
+
+    // an almost singular matrix amplifies the difference between the two channels
+    let mut bad = Spillover::identity(&names);
+    bad.set("FL1-A", "FL2-A", 0.99)?;
+    bad.set("FL2-A", "FL1-A", 0.99)?;
+    let mut compensated = build();
+    compensated.compensate_with(&bad)?;
+    match compensated.compensation_sanity(&original, 2.0) {
+        Err(FcsError::SuspiciousCompensation(parameters)) => assert!(!parameters.is_empty()),
+        other => panic!("expected SuspiciousCompensation, got {:?}", other)
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_spillover_with_scatter_rows() -> Result<(), FcsError> {
+    let spillover = Spillover {
+        parameters: vec!["FSC-A".to_string(), "FL1-A".to_string(), "FL2-A".to_string(), "Time".to_string()],
+        matrix: vec![
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.2, 0.0,
+            0.0, 0.1, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ]
+    };
+    let mut flowdata = FlowData {
+        metadata: Metadata::default(),
+        data: vec![
+            Parameter { id: "FL1-A".to_string(), events: vec![101.0] },
+            Parameter { id: "FL2-A".to_string(), events: vec![30.0] },
+            Parameter { id: "FSC-A".to_string(), events: vec![5.0e4] },
+        ]
+    };
+
+    // only the fluorescence submatrix is used, so the missing Time channel is not needed
+    let names: Vec<&str> = flowdata.data.iter().map(|p| p.id.as_str()).collect();
+    let matrix = CompensationMatrix::new(&spillover, &names)?;
+    assert_eq!(matrix.columns, vec![0, 1]);
+
+    flowdata.compensate_with(&spillover)?;
+    assert!((flowdata.data[0].events[0] - 100.0).abs() < 1e-9);
+    assert!((flowdata.data[1].events[0] - 10.0).abs() < 1e-9);
+    assert_eq!(flowdata.data[2].events[0], 5.0e4);
+
+    // a scatter parameter that does spill into a fluorescence parameter is compensated
+    let spillover = Spillover {
+        parameters: vec!["FL1-A".to_string(), "FSC-A".to_string()],
+        matrix: vec![1.0, 0.0, 0.002, 1.0]
+    };
+    let matrix = CompensationMatrix::new(&spillover, &["FL1-A", "FL2-A", "FSC-A"])?;
+    assert_eq!(matrix.columns, vec![0, 2]);
+    flowdata.compensate_with(&spillover)?;
+    assert!((flowdata.data[0].events[0] - 0.0).abs() < 1e-9);
+    assert_eq!(flowdata.data[2].events[0], 5.0e4);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_is_compensated() -> Result<(), FcsError> {
+    let names = vec!["FL1-A".to_string(), "FL2-A".to_string()];
+    let mut metadata = Metadata::default();
+    assert!(!metadata.is_compensated());
+
+    metadata.set_spillover(&Spillover::identity(&names));
+    assert!(metadata.is_compensated());
+
+    let mut spillover = Spillover::identity(&names);
+    spillover.set("FL1-A", "FL2-A", 0.15)?;
+    metadata.set_spillover(&spillover);
+    assert!(!metadata.is_compensated());
+    assert_eq!(Spillover::from_keyword_value(&metadata.values["$SPILLOVER"])?, spillover);
+
+    let mut metadata = Metadata::default();
+    metadata.insert("$COMP", "2,1,0,0,1");
+    assert!(metadata.is_compensated());
+    metadata.insert("$COMP", "2,1,0.1,0,1");
+    assert!(!metadata.is_compensated());
+    // matrix sizes whose square overflows are not valid matrices
+    metadata.insert("$COMP", "1e30,1");
+    assert!(!metadata.is_compensated());
+    metadata.insert("$COMP", "4294967296,1");
+    assert!(!metadata.is_compensated());
+    let huge = format!("{},FL1-A,1", usize::MAX);
+    assert!(matches!(Spillover::from_keyword_value(&huge), Err(FcsError::InvalidSpillover(_))));
+    let mut spilled = Metadata::default();
+    spilled.insert("$SPILLOVER", &huge);
+    assert!(!spilled.is_compensated());
+
+    let mut metadata = Metadata::default();
+    metadata.insert("$ORIGINALITY", "DataModified");
+    assert!(metadata.is_compensated());
+
+    Ok(())
+}
+
+#[test]
+pub fn test_residual_spillover() -> Result<(), FcsError> {
+    let names = vec!["FL1-A".to_string(), "FL2-A".to_string()];
+    let mut spillover = Spillover::identity(&names);
+    spillover.set("FL1-A", "FL2-A", 0.2)?;
+    spillover.set("FL2-A", "FL1-A", 0.05)?;
+
+    // two uncorrelated populations observed through the spillover matrix
+    let fl1 = [100.0, 400.0, 100.0, 400.0];
+    let fl2 = [50.0, 50.0, 300.0, 300.0];
+    let flowdata = FlowData {
+        metadata: Metadata::default(),
+        data: vec![
+            Parameter { id: "FL1-A".to_string(), events: fl1.iter().zip(fl2.iter()).map(|(a, b)| a + 0.05 * b).collect() },
+            Parameter { id: "FL2-A".to_string(), events: fl1.iter().zip(fl2.iter()).map(|(a, b)| 0.2 * a + b).collect() },
+        ]
+    };
+
+    let residual = flowdata.residual_spillover(&spillover)?;
+    assert_eq!(residual[0][0], 1.0);
+    assert!(residual[0][1].abs() < 1e-9, "{:?}", residual);
+    assert!(residual[1][0].abs() < 1e-9, "{:?}", residual);
+
+    // without compensation the spillover is left in the data
+    let residual = flowdata.residual_spillover(&Spillover::identity(&names))?;
+    assert!(residual[0][1] > 0.1 && residual[1][0] > 0.01, "{:?}", residual);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_metadata_spillover() -> Result<(), FcsError> {
+    let mut metadata = Metadata::default();
+    for (keyword, value) in [("$PAR", "3"), ("$P1N", "FSC-A"), ("$P2N", "FL1-A"), ("$P3N", "FL2-A")] {
+        metadata.insert(keyword, value);
+    }
+    assert!(metadata.spillover().is_none());
+
+    // labels written in another case, or as parameter numbers, are matched back to $PnN
+    metadata.insert("$SPILLOVER", "2,fl1-a,3,1,0.2,0.1,1");
+    let spillover = metadata.spillover().unwrap()?;
+    assert_eq!(spillover.parameters, vec!["FL1-A", "FL2-A"]);
+    assert_eq!(spillover.matrix, vec![1.0, 0.2, 0.1, 1.0]);
+    assert_eq!(spillover.coefficient("FL1-A", "FL2-A"), Some(0.2));
+
+    metadata.insert("$SPILLOVER", "2,FL1-A,FL3-A,1,0.2,0.1,1");
+    assert!(matches!(metadata.spillover(), Some(Err(FcsError::ParameterNotFound(_)))));
+    metadata.insert("$SPILLOVER", "2,FL1-A,FL2-A,1,0.2,0.1");
+    assert!(matches!(metadata.spillover(), Some(Err(FcsError::InvalidSpillover(_)))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_compensate() -> Result<(), FcsError> {
+    let mut metadata = Metadata::default();
+    for (keyword, value) in [("$PAR", "3"), ("$P1N", "FSC-A"), ("$P2N", "FL1-A"), ("$P3N", "FL2-A")] {
+        metadata.insert(keyword, value);
+    }
+    let mut flowdata = FlowData {
+        metadata,
+        data: vec![
+            Parameter { id: "FSC-A".to_string(), events: vec![1000.0, 2000.0] },
+            Parameter { id: "FL1-A".to_string(), events: vec![100.0, 0.0] },
+            Parameter { id: "FL2-A".to_string(), events: vec![25.0, 50.0] },
+        ]
+    };
+    assert!(matches!(flowdata.compensate(), Err(FcsError::MissingKeyword(_))));
+
+    // observed = true * S with S = [[1, 0.25], [0, 1]], so FL2 loses a quarter of FL1
+    flowdata.metadata.insert("$SPILLOVER", "2,FL1-A,FL2-A,1,0.25,0,1");
+    flowdata.compensate()?;
+    assert_eq!(flowdata.data[0].events, vec![1000.0, 2000.0]);
+    assert_eq!(flowdata.data[1].events, vec![100.0, 0.0]);
+    assert_eq!(flowdata.data[2].events, vec![0.0, 50.0]);
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use flowfairy_api::{OrderedFloat, Parameter, DEFAULT_ROBUST_MAX_PERCENTILE};
+
+#[test]
+pub fn test_quantile_normalize() {
+    let mut parameter = Parameter {
+        id: "FL1-A".to_string(),
+        events: vec![40.0, 10.0, 50.0, 30.0, 20.0]
+    };
+    parameter.quantile_normalize(&[100.0, 0.0]);
+    assert_eq!(parameter.events, vec![75.0, 0.0, 100.0, 50.0, 25.0]);
+
+    // the normalized quantiles match the reference quantiles
+    let reference = [3.0, 1.0, 4.0, 1.5, 9.0, 2.6, 5.3];
+    let mut parameter = Parameter {
+        id: "FL2-A".to_string(),
+        events: vec![700.0, -2.0, 55.0, 13.0, 8.0, 1000.0, 21.0]
+    };
+    parameter.quantile_normalize(&reference);
+    let mut normalized = parameter.events.clone();
+    normalized.sort_by(|a, b| a.total_cmp(b));
+    let mut sorted_reference = reference.to_vec();
+    sorted_reference.sort_by(|a, b| a.total_cmp(b));
+    assert_eq!(normalized, sorted_reference);
+
+    // ties share a value
+    let mut parameter = Parameter {
+        id: "FL3-A".to_string(),
+        events: vec![1.0, 1.0, 2.0]
+    };
+    parameter.quantile_normalize(&[0.0, 10.0, 20.0]);
+    assert_eq!(parameter.events, vec![5.0, 5.0, 20.0]);
+}
+
+#[test]
+pub fn test_mad() {
+    let parameter = Parameter {
+        id: "FL1-A".to_string(),
+        events: vec![1.0, 1.0, 2.0, 2.0, 4.0, 6.0, 9.0]
+    };
+    // median 2, absolute deviations 1, 1, 0, 0, 2, 4, 7
+    assert_eq!(parameter.mad(), 1.0);
+
+    let parameter = Parameter {
+        id: "FL2-A".to_string(),
+        events: vec![3.0, -3.0, 1000.0, 0.0]
+    };
+    // median 1.5, absolute deviations 1.5, 4.5, 998.5, 1.5
+    assert_eq!(parameter.mad(), 3.0);
+
+    let empty = Parameter { id: "FL3-A".to_string(), events: vec![] };
+    assert!(empty.mad().is_nan());
+}
+
+#[test]
+pub fn test_robust_max() {
+    let mut events: Vec<f64> = (0..=1000).map(|i| i as f64).collect();
+    events.push(1.0e9);
+    let parameter = Parameter { id: "FL1-A".to_string(), events };
+
+    // the outlier is far above the 99th percentile
+    let max = parameter.robust_max(DEFAULT_ROBUST_MAX_PERCENTILE);
+    assert!(max > 985.0 && max < 1000.0, "{}", max);
+    assert_eq!(parameter.robust_max(100.0), 1.0e9);
+    assert_eq!(parameter.robust_max(0.0), 0.0);
+
+    let empty = Parameter { id: "FL2-A".to_string(), events: vec![] };
+    assert!(empty.robust_max(99.0).is_nan());
+}
+
+#[test]
+pub fn test_value_counts() {
+    let parameter = Parameter {
+        id: "Sort Classifier".to_string(),
+        events: vec![0.0, 2.0, 1.0, 2.0, -0.0, 2.0, f64::NAN, 1.0]
+    };
+
+    let counts = parameter.value_counts();
+    assert_eq!(counts.len(), 4);
+    assert_eq!(counts[&OrderedFloat(0.0)], 2);
+    assert_eq!(counts[&OrderedFloat(1.0)], 2);
+    assert_eq!(counts[&OrderedFloat(2.0)], 3);
+    assert_eq!(counts[&OrderedFloat(f64::NAN)], 1);
+    assert_eq!(counts.values().sum::<usize>(), parameter.events.len());
+
+    let mut values: Vec<OrderedFloat<f64>> = counts.into_keys().collect();
+    values.sort();
+    assert_eq!(values[0], OrderedFloat(0.0));
+    assert!(values[3].0.is_nan());
+}
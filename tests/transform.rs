@@ -0,0 +1,190 @@
+mod common;
+
+use flowfairy_api::{read_fcs, FcsError, FlowData, Metadata, NanPolicy, Parameter};
+
+#[test]
+pub fn test_apply_recommended_scaling() -> Result<(), FcsError> {
+    let mut metadata = Metadata::default();
+    for (keyword, value) in [
+        ("$P1N", "FL1-A"), ("$P1E", "4,1"), ("$P1R", "1024"),
+        ("$P2N", "FSC-A"), ("$P2E", "0,0"), ("$P2R", "1024"),
+        ("$P3N", "FL2-A"), ("$P3E", "0,0"), ("$P3R", "1024"), ("$P3D", "Logarithmic,4,1"),
+        ("$P4N", "SSC-A"), ("$P4E", "0,0"), ("$P4R", "1024"), ("$P4D", "Linear,0,1024"),
+    ] {
+        metadata.insert(keyword, value);
+    }
+    let mut flowdata = FlowData {
+        metadata,
+        data: vec![
+            Parameter { id: "FL1-A".to_string(), events: vec![0.0, 512.0, 1024.0] },
+            Parameter { id: "FSC-A".to_string(), events: vec![0.0, 512.0, 1024.0] },
+            Parameter { id: "FL2-A".to_string(), events: vec![10.0, 100.0, 0.5] },
+            Parameter { id: "SSC-A".to_string(), events: vec![10.0, 100.0, 0.5] },
+        ]
+    };
+
+    flowdata.apply_recommended_scaling()?;
+    let expected = [1.0, 100.0, 10000.0];
+    for (value, expected) in flowdata.data[0].events.iter().zip(expected) {
+        assert!((value - expected).abs() < 1e-9);
+    }
+    assert_eq!(flowdata.data[1].events, vec![0.0, 512.0, 1024.0]);
+    assert_eq!(flowdata.data[2].events, vec![1.0, 2.0, 0.0]);
+    assert_eq!(flowdata.data[3].events, vec![10.0, 100.0, 0.5]);
+    assert_eq!(flowdata.metadata.values["$P1E"], "0,0");
+    assert!(!flowdata.metadata.values.contains_key("$P3D"));
+    assert_eq!(flowdata.metadata.values["$P4D"], "Linear,0,1024");
+
+    // scaling again leaves the scaled parameters untouched
+    let scaled: Vec<Vec<f64>> = flowdata.data.iter().map(|p| p.events.clone()).collect();
+    flowdata.apply_recommended_scaling()?;
+    for (parameter, scaled) in flowdata.data.iter().zip(scaled.iter()) {
+        assert_eq!(&parameter.events, scaled);
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_map_parameter() -> Result<(), FcsError> {
+    let mut flowdata = FlowData {
+        metadata: Metadata::default(),
+        data: vec![
+            Parameter { id: "FSC-A".to_string(), events: vec![1.0, -2.0, 3.0] },
+            Parameter { id: "SSC-A".to_string(), events: vec![4.0, 5.0, 6.0] },
+        ]
+    };
+
+    flowdata.map_parameter("FSC-A", |x| x * x)?;
+    assert_eq!(flowdata.data[0].events, vec![1.0, 4.0, 9.0]);
+    assert_eq!(flowdata.data[1].events, vec![4.0, 5.0, 6.0]);
+
+    flowdata.data[1].map_events(|x| x * x);
+    assert_eq!(flowdata.data[1].events, vec![16.0, 25.0, 36.0]);
+
+    assert!(matches!(flowdata.map_parameter("FL1-A", |x| x), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_log_transform_nan_policy() -> Result<(), FcsError> {
+    let build = || FlowData {
+        metadata: Metadata::default(),
+        data: vec![Parameter { id: "FL1-A".to_string(), events: vec![0.0, 10.0, -5.0, 1000.0] }]
+    };
+
+    let mut flowdata = build();
+    flowdata.log_transform("FL1-A", NanPolicy::Propagate)?;
+    let events = &flowdata.data[0].events;
+    assert!(events[0].is_nan() && events[2].is_nan());
+    assert_eq!((events[1], events[3]), (1.0, 3.0));
+
+    let mut flowdata = build();
+    flowdata.log_transform("FL1-A", NanPolicy::ClampToMin)?;
+    assert_eq!(flowdata.data[0].events, vec![1.0, 1.0, 1.0, 3.0]);
+
+    let mut flowdata = build();
+    flowdata.log_transform("FL1-A", NanPolicy::SetTo(-1.0))?;
+    assert_eq!(flowdata.data[0].events, vec![-1.0, 1.0, -1.0, 3.0]);
+
+    assert!(matches!(flowdata.log_transform("FL2-A", NanPolicy::Propagate), Err(FcsError::ParameterNotFound(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_transforms_with_policy() -> Result<(), FcsError> {
+    let parameter = Parameter { id: "FL1-A".to_string(), events: vec![f64::NAN, 5.0, f64::INFINITY] };
+    let transformed = parameter.arcsinh_with_policy(5.0, NanPolicy::SetTo(0.0))?;
+    assert_eq!(transformed.events[0], 0.0);
+    assert!((transformed.events[1] - 0.881373587019543).abs() < 1e-12);
+    assert_eq!(transformed.events[2], 0.0);
+    assert!(parameter.arcsinh(5.0)?.events[0].is_nan());
+
+    let transformed = parameter.logicle_with_policy(262144.0, 0.5, 4.5, 0.0, NanPolicy::ClampToMin)?;
+    assert!(transformed.events.iter().all(|value| value.is_finite()));
+    assert_eq!(transformed.events[0], transformed.events[1]);
+
+    let mut flowdata = FlowData {
+        metadata: Metadata::default(),
+        data: vec![Parameter { id: "FL1-A".to_string(), events: vec![-1.0, 4.0, 9.0] }]
+    };
+    flowdata.map_parameter_with_policy("FL1-A", |x| x.sqrt(), NanPolicy::ClampToMin)?;
+    assert_eq!(flowdata.data[0].events, vec![2.0, 2.0, 3.0]);
+
+    let mut metadata = Metadata::default();
+    for (keyword, value) in [("$P1N", "FL1-A"), ("$P1E", "0,0"), ("$P1R", "1024"), ("$P1D", "Logarithmic,4,0")] {
+        metadata.insert(keyword, value);
+    }
+    let mut flowdata = FlowData {
+        metadata,
+        data: vec![Parameter { id: "FL1-A".to_string(), events: vec![0.0, 100.0] }]
+    };
+    flowdata.apply_recommended_scaling_with_policy(NanPolicy::SetTo(-1.0))?;
+    assert_eq!(flowdata.data[0].events, vec![-1.0, 2.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_arcsinh_and_logicle() -> Result<(), FcsError> {
+    let parameter = Parameter { id: "FL1-A".to_string(), events: vec![-500.0, 0.0, 5.0, 500.0] };
+
+    let transformed = parameter.arcsinh(5.0)?;
+    assert_eq!(transformed.id, "FL1-A");
+    let expected = [-5.298342365610589, 0.0, 0.881373587019543, 5.298342365610589];
+    for (value, expected) in transformed.events.iter().zip(expected) {
+        assert!((value - expected).abs() < 1e-12);
+    }
+    assert!(matches!(parameter.arcsinh(0.0), Err(FcsError::InvalidArgument(_))));
+
+    // reference values from the inverse S(y) = a e^(by) - c e^(-dy) + f of Moore and Parks,
+    // "Update for the logicle data scale including operational code implementations",
+    // Cytometry A 81A (2012), solved for y by bisection at 40 digits with mpmath and rounded
+    let check = |(t, w, m, a): (f64, f64, f64, f64), points: &[(f64, f64)]| -> Result<(), FcsError> {
+        let parameter = Parameter { id: "FL1-A".to_string(), events: points.iter().map(|(x, _)| *x).collect() };
+        let transformed = parameter.logicle(t, w, m, a)?;
+        for (value, (_, expected)) in transformed.events.iter().zip(points) {
+            assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+        }
+        Ok(())
+    };
+    check((262144.0, 0.5, 4.5, 0.0), &[
+        (-100.0, 0.009041134692025), (0.0, 0.111111111111111), (10.0, 0.122304275677448),
+        (100.0, 0.213181087530197), (1000.0, 0.454337576172399), (10000.0, 0.683832657226557),
+        (262144.0, 1.0),
+    ])?;
+    check((10000.0, 1.0, 4.5, 1.0), &[
+        (-1000.0, -0.088220521048976), (-10.0, 0.315899374976449), (0.0, 0.363636363636364),
+        (50.0, 0.542262399548883), (5000.0, 0.944943347919595),
+    ])?;
+    assert!(matches!(parameter.logicle(262144.0, 3.0, 4.5, 0.0), Err(FcsError::InvalidArgument(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_linearize() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("I", "1,2", 3, &[("FL1-H", "16", "1024"), ("FSC-H", "16", "1024")]);
+    common::set_keyword(&mut keywords, "$P1E", "4,0");
+    let data: Vec<u8> = [0u16, 100, 256, 200, 768, 300].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &data);
+    let path = common::write_fixture("linearize", &bytes);
+
+    let mut flowdata = read_fcs(&path)?;
+    flowdata.linearize()?;
+    // an offset of 0 is read as 1, so channels 0..1024 span 1..10^4
+    let expected = [1.0, 10.0, 1000.0];
+    for (value, expected) in flowdata.data[0].events.iter().zip(expected) {
+        assert!((value - expected).abs() < 1e-9);
+    }
+    assert_eq!(flowdata.data[1].events, vec![100.0, 200.0, 300.0]);
+    assert_eq!(flowdata.metadata.values["$P1E"], "0,0");
+
+    // already linear data is left as is
+    flowdata.linearize()?;
+    assert!((flowdata.data[0].events[2] - 1000.0).abs() < 1e-9);
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::env;
+use flowfairy_api::{read_fcs, read_fcs_from_bytes, validate_metadata, write_fcs, FcsError};
+
+#[test]
+pub fn test_convert_version() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("D", "4,3,2,1", 2, &[("FSC-A", "64", "1024"), ("SSC-A", "64", "1024")]);
+    common::remove_keyword(&mut keywords, "$P2E");
+    common::remove_keyword(&mut keywords, "$MODE");
+    let data: Vec<u8> = [1.5f64, -2.0, 3.25, 4.0].iter().flat_map(|v| v.to_be_bytes()).collect();
+    let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+
+    let mut flowdata = read_fcs_from_bytes(&bytes)?;
+    flowdata.convert_version("FCS3.0")?;
+    assert_eq!(flowdata.metadata.version, "FCS3.0");
+    assert_eq!(flowdata.header().version, "FCS3.0");
+    assert_eq!(flowdata.metadata.values["$BYTEORD"], "8,7,6,5,4,3,2,1");
+    assert_eq!(flowdata.metadata.values["$P2E"], "0,0");
+    assert_eq!(flowdata.metadata.values["$MODE"], "L");
+    validate_metadata(&flowdata.metadata)?;
+
+    // the converted data set is written and read back as a valid FCS 3.0 file
+    let path = env::temp_dir().join("flowfairy_convert_version.fcs");
+    let path = path.to_str().unwrap();
+    write_fcs(&flowdata, path)?;
+    let converted = read_fcs(path)?;
+    assert_eq!(converted.metadata.version, "FCS3.0");
+    assert!(converted.metadata.warnings.is_empty());
+    assert_eq!(converted.data[0].events, vec![1.5, 3.25]);
+    assert_eq!(converted.data[1].events, vec![-2.0, 4.0]);
+
+    // and back to 3.1, which only takes the endianness in $BYTEORD
+    flowdata.convert_version("FCS3.1")?;
+    assert_eq!(flowdata.metadata.values["$BYTEORD"], "4,3,2,1");
+    validate_metadata(&flowdata.metadata)?;
+
+    assert!(matches!(flowdata.convert_version("FCS4.0"), Err(FcsError::UnsupportedVersion(_))));
+
+    // a mixed byte order has no plain equivalent
+    flowdata.metadata.insert("$BYTEORD", "3,4,1,2");
+    assert!(matches!(flowdata.convert_version("FCS3.0"), Err(FcsError::UnsupportedByteOrder { .. })));
+    assert_eq!(flowdata.metadata.version, "FCS3.1");
+    assert_eq!(flowdata.metadata.values["$BYTEORD"], "3,4,1,2");
+    Ok(())
+}
+
+#[test]
+pub fn test_convert_version_segment_keywords() -> Result<(), FcsError> {
+    // FCS 2.0 stores the segment offsets in the header only
+    let mut keywords = common::list_mode_keywords("I", "1,2", 2, &[("FSC-H", "16", "1024"), ("SSC-H", "16", "1024")]);
+    for keyword in ["$BEGINANALYSIS", "$ENDANALYSIS", "$BEGINSTEXT", "$ENDSTEXT", "$BEGINDATA", "$ENDDATA", "$P1E", "$P2E", "$MODE"] {
+        common::remove_keyword(&mut keywords, keyword);
+    }
+    let data: Vec<u8> = [100u16, 200, 300, 400].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let bytes = common::build_fcs("FCS2.0", b'|', &keywords, &data);
+
+    let mut flowdata = read_fcs_from_bytes(&bytes)?;
+    let data_start = flowdata.header().data_start;
+    flowdata.convert_version("FCS3.1")?;
+    validate_metadata(&flowdata.metadata)?;
+    assert_eq!(flowdata.metadata.values["$BEGINDATA"], data_start.to_string());
+    assert_eq!(flowdata.metadata.values["$ENDDATA"], (data_start + 7).to_string());
+    assert_eq!(flowdata.metadata.values["$BEGINSTEXT"], "0");
+    assert_eq!(flowdata.metadata.values["$ENDANALYSIS"], "0");
+    assert_eq!(flowdata.metadata.values["$BYTEORD"], "1,2,3,4");
+
+    let path = env::temp_dir().join("flowfairy_convert_version_3_1.fcs");
+    let path = path.to_str().unwrap();
+    write_fcs(&flowdata, path)?;
+    let converted = read_fcs(path)?;
+    assert_eq!(converted.metadata.version, "FCS3.1");
+    assert_eq!(converted.data[1].events, vec![200.0, 400.0]);
+
+    // and back to 2.0, which does not know the offset keywords
+    flowdata.convert_version("FCS2.0")?;
+    validate_metadata(&flowdata.metadata)?;
+    assert!(!flowdata.metadata.values.contains_key("$BEGINDATA"));
+    assert!(!flowdata.metadata.keywords.iter().any(|keyword| keyword == "$ENDSTEXT"));
+    write_fcs(&flowdata, path)?;
+    let converted = read_fcs(path)?;
+    assert_eq!(converted.metadata.version, "FCS2.0");
+    assert!(!converted.metadata.values.contains_key("$BEGINDATA"));
+    assert_eq!(converted.data[0].events, vec![100.0, 300.0]);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_convert_version_mixed_integer_byte_order() -> Result<(), FcsError> {
+    // a mixed order is valid up to FCS 3.0 for every data type, and never from 3.1 on
+    let keywords = common::list_mode_keywords("I", "3,4,1,2", 1, &[("FSC-H", "32", "1024")]);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::int_bytes(70000, &[3, 4, 1, 2]));
+
+    let mut flowdata = read_fcs_from_bytes(&bytes)?;
+    assert!(matches!(flowdata.convert_version("FCS3.1"), Err(FcsError::UnsupportedByteOrder { .. })));
+    assert_eq!(flowdata.metadata.version, "FCS3.0");
+
+    flowdata.convert_version("FCS2.0")?;
+    validate_metadata(&flowdata.metadata)?;
+    assert_eq!(flowdata.metadata.values["$BYTEORD"], "3,4,1,2");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_convert_version_incompatible_keyword() -> Result<(), FcsError> {
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 1, &[("FSC-A", "32", "1024")]);
+    common::set_keyword(&mut keywords, "$FLOWRATE", "1.5");
+    let bytes = common::build_fcs("FCS3.2", b'|', &keywords, &common::f32_le(&[1.0]));
+
+    let mut flowdata = read_fcs_from_bytes(&bytes)?;
+    match flowdata.convert_version("FCS3.0") {
+        Err(FcsError::IncompatibleKeyword { keyword, version }) => {
+            assert_eq!(keyword, "$FLOWRATE");
+            assert_eq!(version, "FCS3.0");
+        },
+        other => panic!("expected IncompatibleKeyword, got {:?}", other)
+    }
+    assert_eq!(flowdata.metadata.version, "FCS3.2");
+
+    flowdata.metadata.values.remove("$FLOWRATE");
+    flowdata.metadata.keywords.retain(|keyword| keyword != "$FLOWRATE");
+    flowdata.metadata.insert("$P1D", "Linear,0,1024");
+    assert!(matches!(flowdata.convert_version("FCS3.0"), Err(FcsError::IncompatibleKeyword { .. })));
+    flowdata.convert_version("FCS3.1")?;
+
+    Ok(())
+}
@@ -0,0 +1,307 @@
+mod common;
+
+use flowfairy_api::{read_fcs, read_fcs_from_bytes, update_keywords_in_place, write_fcs, write_fcs_with_options, DataType, FcsError, WriteOptions};
+use std::collections::HashMap;
+use std::{env, fs};
+
+const FORMAT_3_0_TESTFILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/test_fcs_files/format_3_0.fcs");
+
+#[test]
+pub fn test_escaped_delimitter_round_trip() -> Result<(), FcsError> {
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let delimitter = flowdata.metadata.delimitter as char;
+    let comment = format!("a{}b", delimitter);
+    let specimen = format!("tube{d}{d}1{d}", d = delimitter);
+    flowdata.metadata.insert("$COM", &comment);
+    flowdata.metadata.insert("$SMNO", &specimen);
+
+    let path = env::temp_dir().join("flowfairy_escaped_delimitter.fcs");
+    write_fcs(&flowdata, path.to_str().unwrap())?;
+    let read_back = read_fcs(path.to_str().unwrap())?;
+
+    assert_eq!(read_back.metadata.values.get("$COM").unwrap(), &comment);
+    assert_eq!(read_back.metadata.values.get("$SMNO").unwrap(), &specimen);
+    assert_eq!(read_back.metadata.keywords, flowdata.metadata.keywords);
+    assert_eq!(read_back.data[5].events, flowdata.data[5].events);
+
+    // a value starting with the delimitter cannot be told apart from an escaped delimitter
+    flowdata.metadata.insert("$COM", &format!("{}a", delimitter));
+    assert!(matches!(write_fcs(&flowdata, path.to_str().unwrap()), Err(FcsError::InvalidKeywordValue { .. })));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_update_keywords_in_place() -> Result<(), FcsError> {
+    let path = env::temp_dir().join("flowfairy_update_in_place.fcs");
+    let path = path.to_str().unwrap();
+    fs::copy(FORMAT_3_0_TESTFILE, path)?;
+    let original = read_fcs(path)?;
+
+    // a value of equal length is replaced without moving anything
+    let updates = HashMap::from([("$CYT".to_string(), "LE-MA900FX".to_string())]);
+    update_keywords_in_place(path, &updates)?;
+    let updated = read_fcs(path)?;
+    assert_eq!(updated.metadata.values.get("$CYT").unwrap(), "LE-MA900FX");
+    assert_eq!(updated.metadata.keywords, original.metadata.keywords);
+    assert_eq!(updated.data[5].events, original.data[5].events);
+    assert_eq!(fs::metadata(path)?.len(), fs::metadata(FORMAT_3_0_TESTFILE)?.len());
+
+    // a shorter text segment is padded
+    let updates = HashMap::from([("$CYT".to_string(), "MA900".to_string())]);
+    update_keywords_in_place(path, &updates)?;
+    let updated = read_fcs(path)?;
+    assert_eq!(updated.metadata.values.get("$CYT").unwrap(), "MA900");
+    assert_eq!(updated.data[0].events, original.data[0].events);
+
+    // keywords are matched ignoring case
+    let updates = HashMap::from([("$cyt".to_string(), "MA901".to_string())]);
+    update_keywords_in_place(path, &updates)?;
+    let updated = read_fcs(path)?;
+    assert_eq!(updated.metadata.values.get("$CYT").unwrap(), "MA901");
+    assert_eq!(updated.metadata.keywords, original.metadata.keywords);
+
+    // a longer one does not fit
+    let updates = HashMap::from([("$COM".to_string(), "x".repeat(10_000))]);
+    assert!(matches!(update_keywords_in_place(path, &updates), Err(FcsError::TextSegmentTooLarge { .. })));
+    assert_eq!(read_fcs(path)?.metadata.values.get("$CYT").unwrap(), "MA901");
+
+    Ok(())
+}
+
+#[test]
+pub fn test_update_keywords_in_place_keeps_stored_text() -> Result<(), FcsError> {
+    // a comment with a Windows line break and trailing space, and a supplemental text segment
+    // overriding $DATATYPE, neither of which may leak into the rewritten primary segment
+    let data = common::f64_le(&[1.5, -2.25, 1.0e12, 7.0]);
+    let mut keywords = common::list_mode_keywords("F", "1,2,3,4", 2, &[("FSC-A", "32", "1024"), ("SSC-A", "32", "1024")]);
+    keywords.push(("$COM".to_string(), "first\r\nsecond ".to_string()));
+    common::set_keyword(&mut keywords, "$BEGINSTEXT", "00000000");
+    common::set_keyword(&mut keywords, "$ENDSTEXT", "00000000");
+    let stext = common::build_text(b'/', &[
+        ("$DATATYPE".to_string(), "D".to_string()),
+        ("$BYTEORD".to_string(), "1,2,3,4,5,6,7,8".to_string()),
+    ]);
+    let stext_start = common::build_fcs("FCS3.1", b'|', &keywords, &data).len();
+    common::set_keyword(&mut keywords, "$BEGINSTEXT", &format!("{:08}", stext_start));
+    common::set_keyword(&mut keywords, "$ENDSTEXT", &format!("{:08}", stext_start + stext.len() - 1));
+    let mut bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+    bytes.extend_from_slice(&stext);
+    let path = common::write_fixture("update_in_place_stored_text", &bytes);
+
+    let updates = HashMap::from([("$P1N".to_string(), "FSC-H".to_string())]);
+    update_keywords_in_place(&path, &updates)?;
+    let updated = fs::read(&path)?;
+    assert_eq!(updated.len(), bytes.len());
+    let txt_end: usize = String::from_utf8_lossy(&bytes[18..26]).trim().parse().unwrap();
+    let expected = String::from_utf8_lossy(&bytes[58..=txt_end]).replace("|$P1N|FSC-A|", "|$P1N|FSC-H|");
+    assert_eq!(&updated[58..=txt_end], expected.as_bytes());
+    assert_eq!(&updated[txt_end + 1..], &bytes[txt_end + 1..]);
+
+    let flowdata = read_fcs(&path)?;
+    assert_eq!(flowdata.data[0].id, "FSC-H");
+    assert_eq!(flowdata.data[0].events, vec![1.5, 1.0e12]);
+
+    // segment offsets and the data layout cannot be changed without rewriting the segments
+    for keyword in ["$BEGINDATA", "$enddata", "$NEXTDATA", "$BEGINSTEXT", "$ENDSTEXT", "$TOT", "$Par", "$DATATYPE", "$BYTEORD", "$P2B", "$p1b"] {
+        let updates = HashMap::from([(keyword.to_string(), "0".to_string())]);
+        assert!(matches!(update_keywords_in_place(&path, &updates), Err(FcsError::InvalidArgument(_))));
+    }
+    assert_eq!(fs::read(&path)?, updated);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_recompute_data_offsets() -> Result<(), FcsError> {
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let mut subsampled = flowdata.gate_rectangle("TIME", "FSC-A", (0.0, 1.0e9), (0.0, 5.0e4))?;
+    let total_events = subsampled.data[0].events.len();
+    assert!(total_events < flowdata.data[0].events.len());
+
+    subsampled.recompute_data_offsets()?;
+    let offset = |keyword: &str| subsampled.metadata.values.get(keyword).unwrap().parse::<usize>().unwrap();
+    assert_eq!(offset("$ENDDATA") - offset("$BEGINDATA") + 1, total_events * 6 * 4);
+    assert_eq!(offset("$TOT"), total_events);
+    let header = subsampled.header();
+    assert_eq!(header.data_start as usize, offset("$BEGINDATA"));
+    assert_eq!(header.data_end as usize, offset("$ENDDATA"));
+    assert_eq!(header.txt_end as usize, offset("$BEGINDATA") - 1);
+
+    // the offsets match the layout written by write_fcs
+    let path = env::temp_dir().join("flowfairy_recompute_offsets.fcs");
+    write_fcs(&subsampled, path.to_str().unwrap())?;
+    let read_back = read_fcs(path.to_str().unwrap())?;
+    assert_eq!(read_back.metadata.values.get("$BEGINDATA"), subsampled.metadata.values.get("$BEGINDATA"));
+    assert_eq!(read_back.metadata.values.get("$ENDDATA"), subsampled.metadata.values.get("$ENDDATA"));
+    assert_eq!(fs::metadata(&path)?.len() as usize, offset("$ENDDATA") + 1);
+    assert_eq!(read_back.header(), header);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_mixed_integer_widths_round_trip() -> Result<(), FcsError> {
+    // every parameter is read with the width of its own $PnB
+    let params = [("FSC-H", "16", "65536"), ("SSC-H", "32", "4294967296"), ("FL1-H", "8", "256"), ("Time", "64", "0")];
+    let events: [[u64; 4]; 2] = [[65535, 70000, 200, 1 << 40], [3, 4_000_000_000, 0, 12]];
+    for (byte_order, order) in [("1,2,3,4", [1usize, 2, 3, 4, 5, 6, 7, 8]), ("4,3,2,1", [8, 7, 6, 5, 4, 3, 2, 1])] {
+        let keywords = common::list_mode_keywords("I", byte_order, 2, &params);
+        let data: Vec<u8> = events.iter()
+            .flat_map(|event| event.iter().zip(params.iter()).map(|(&value, (_, bits, _))| (value, bits.parse::<usize>().unwrap() / 8)))
+            .flat_map(|(value, width)| {
+                let order: Vec<usize> = order.iter().copied().filter(|&b| b <= width).collect();
+                common::int_bytes(value, &order)
+            })
+            .collect();
+        let bytes = common::build_fcs("FCS3.1", b'|', &keywords, &data);
+        let path = common::write_fixture(&format!("mixed_integer_widths_{}", byte_order), &bytes);
+
+        let flowdata = read_fcs(&path)?;
+        for (i, parameter) in flowdata.data.iter().enumerate() {
+            assert_eq!(parameter.events, vec![events[0][i] as f64, events[1][i] as f64], "{}", parameter.id);
+        }
+
+        let path = env::temp_dir().join(format!("flowfairy_mixed_integer_widths_{}.fcs", byte_order));
+        write_fcs(&flowdata, path.to_str().unwrap())?;
+        let read_back = read_fcs(path.to_str().unwrap())?;
+        for (parameter, original) in read_back.data.iter().zip(flowdata.data.iter()) {
+            assert_eq!(parameter.events, original.events);
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+pub fn test_float_64_bit_parameters_round_trip() -> Result<(), FcsError> {
+    // $DATATYPE F with $PnB 64 is read as doubles, and written back the same way
+    let events = [1.0e-10, 250000.125, 2.5, -7.75, 3.0e10, 0.1];
+    let keywords = common::list_mode_keywords("F", "1,2,3,4", 3, &[("FSC-A", "64", "1024"), ("SSC-A", "64", "1024")]);
+    let bytes = common::build_fcs("FCS3.0", b'|', &keywords, &common::f64_le(&events));
+    let path = common::write_fixture("float_64_bit_parameters_round_trip", &bytes);
+    let flowdata = read_fcs(&path)?;
+
+    let path = env::temp_dir().join("flowfairy_float_64_bit_parameters.fcs");
+    write_fcs(&flowdata, path.to_str().unwrap())?;
+    let read_back = read_fcs(path.to_str().unwrap())?;
+    assert_eq!(read_back.metadata.values["$P1B"], "64");
+    assert_eq!(read_back.data[0].events, vec![1.0e-10, 2.5, 3.0e10]);
+    assert_eq!(read_back.data[1].events, vec![250000.125, -7.75, 0.1]);
+    // the doubles are stored unchanged
+    assert!(fs::read(&path)?.ends_with(&bytes[bytes.len() - 48..]));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_to_interleaved_bytes() -> Result<(), FcsError> {
+    let flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let params: Vec<(String, String, String)> = flowdata.data.iter()
+        .map(|p| (p.id.to_owned(), "32".to_string(), "262144".to_string()))
+        .collect();
+    let params: Vec<(&str, &str, &str)> = params.iter().map(|(n, b, r)| (n.as_str(), b.as_str(), r.as_str())).collect();
+    let total_events = flowdata.data[0].events.len();
+
+    for (data_type, byte_order) in [(DataType::Float, "4,3,2,1"), (DataType::Double, "1,2,3,4")] {
+        let bytes = flowdata.to_interleaved_bytes(data_type, byte_order)?;
+        let width = if data_type == DataType::Float { 4 } else { 8 };
+        assert_eq!(bytes.len(), total_events * flowdata.data.len() * width);
+
+        let keywords = common::list_mode_keywords(data_type.keyword_value(), byte_order, total_events, &params);
+        let decoded = read_fcs_from_bytes(&common::build_fcs("FCS3.1", b'|', &keywords, &bytes))?;
+        for (parameter, original) in decoded.data.iter().zip(flowdata.data.iter()) {
+            assert_eq!(parameter.events, original.events);
+        }
+    }
+
+    assert!(matches!(flowdata.to_interleaved_bytes(DataType::Ascii, "1,2,3,4"), Err(FcsError::UnsupportedDataType(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_write_with_delimitter() -> Result<(), FcsError> {
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    flowdata.metadata.insert("$COM", "a/b//c");
+
+    let path = env::temp_dir().join("flowfairy_custom_delimitter.fcs");
+    let opts = WriteOptions { delimitter: Some(b'/') };
+    write_fcs_with_options(&flowdata, path.to_str().unwrap(), &opts)?;
+    let read_back = read_fcs(path.to_str().unwrap())?;
+
+    assert_eq!(read_back.metadata.delimitter, b'/');
+    assert_eq!(fs::read(&path)?[58], b'/');
+    assert_eq!(read_back.metadata.values.get("$COM").unwrap(), "a/b//c");
+    assert_eq!(read_back.metadata.keywords, flowdata.metadata.keywords);
+    assert_eq!(read_back.data[5].events, flowdata.data[5].events);
+
+    // a value starting with the chosen delimitter cannot be written
+    flowdata.metadata.insert("$COM", "/a");
+    assert!(matches!(write_fcs_with_options(&flowdata, path.to_str().unwrap(), &opts), Err(FcsError::InvalidKeywordValue { .. })));
+    let opts = WriteOptions { delimitter: Some(0) };
+    assert!(matches!(write_fcs_with_options(&flowdata, path.to_str().unwrap(), &opts), Err(FcsError::InvalidArgument(_))));
+
+    Ok(())
+}
+
+#[test]
+pub fn test_write_round_trip() -> Result<(), FcsError> {
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    flowdata.metadata.insert("$CYT", "round trip cytometer");
+    flowdata.metadata.insert("$PROJ", "flowfairy");
+
+    let path = env::temp_dir().join("flowfairy_round_trip.fcs");
+    write_fcs(&flowdata, path.to_str().unwrap())?;
+    let read_back = read_fcs(path.to_str().unwrap())?;
+
+    // only the segment offsets are recomputed for the written layout
+    let offsets = ["$BEGINDATA", "$ENDDATA", "$BEGINANALYSIS", "$ENDANALYSIS", "$BEGINSTEXT", "$ENDSTEXT", "$NEXTDATA"];
+    assert_eq!(read_back.metadata.version, flowdata.metadata.version);
+    assert_eq!(read_back.metadata.keywords, flowdata.metadata.keywords);
+    for keyword in flowdata.metadata.keywords.iter().filter(|k| !offsets.contains(&k.as_str())) {
+        assert_eq!(read_back.metadata.values.get(keyword), flowdata.metadata.values.get(keyword), "{}", keyword);
+    }
+    for (parameter, original) in read_back.data.iter().zip(flowdata.data.iter()) {
+        assert_eq!(parameter.id, original.id);
+        assert_eq!(parameter.events, original.events);
+    }
+
+    // the header offsets point at the written segments
+    let header = read_back.header();
+    let begin_data = read_back.metadata.values.get("$BEGINDATA").unwrap().parse::<u64>().unwrap();
+    assert_eq!(header.txt_start, 58);
+    assert_eq!(header.txt_end + 1, begin_data);
+    assert_eq!(header.data_start, begin_data);
+    assert_eq!(header.data_end + 1, fs::metadata(&path)?.len());
+
+    // writing what was read back gives the same file
+    let again = env::temp_dir().join("flowfairy_round_trip_again.fcs");
+    write_fcs(&read_back, again.to_str().unwrap())?;
+    assert_eq!(fs::read(&path)?, fs::read(&again)?);
+
+    Ok(())
+}
+
+#[test]
+pub fn test_data_digest() -> Result<(), FcsError> {
+    let mut flowdata = read_fcs(FORMAT_3_0_TESTFILE)?;
+    let digest = flowdata.data_digest();
+    assert_eq!(digest, read_fcs(FORMAT_3_0_TESTFILE)?.data_digest());
+
+    let path = env::temp_dir().join("flowfairy_data_digest.fcs");
+    write_fcs(&flowdata, path.to_str().unwrap())?;
+    assert_eq!(read_fcs(path.to_str().unwrap())?.data_digest(), digest);
+
+    flowdata.data[2].events[7] += 1.0;
+    assert_ne!(flowdata.data_digest(), digest);
+    flowdata.data[2].events[7] -= 1.0;
+    assert_eq!(flowdata.data_digest(), digest);
+
+    // moving an event to the neighbouring parameter changes the digest
+    let event = flowdata.data[0].events.pop().unwrap();
+    flowdata.data[1].events.insert(0, event);
+    assert_ne!(flowdata.data_digest(), digest);
+
+    Ok(())
+}